@@ -0,0 +1,253 @@
+use crate::Report;
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("I/O error in {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("Invalid baseline {0}: {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("Unsupported baseline version {1} in {0}")]
+    Version(PathBuf, u32),
+}
+
+const VERSION: u32 = 1;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct BaselineFile {
+    version: u32,
+    findings: Vec<Entry>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    path: PathBuf,
+    rule: String,
+    fingerprint: String,
+    count: usize,
+    /// Message of the finding, only informative for human readers.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    message: String,
+}
+
+type Key = (PathBuf, String, String);
+
+/// Findings which were accepted and are not reported again.
+///
+/// Findings are identified by path, rule and a fingerprint of the matched text
+/// instead of their position, so that unrelated edits which shift lines do not
+/// invalidate the baseline.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    known: BTreeMap<Key, (usize, String)>,
+}
+
+impl Baseline {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        let file: BaselineFile =
+            serde_json::from_str(&source).map_err(|e| Error::Parse(path.to_owned(), e))?;
+        if file.version != VERSION {
+            return Err(Error::Version(path.to_owned(), file.version));
+        }
+        let mut baseline = Self::default();
+        for e in file.findings {
+            let known = baseline
+                .known
+                .entry((e.path, e.rule, e.fingerprint))
+                .or_insert((0, e.message));
+            known.0 += e.count;
+        }
+        Ok(baseline)
+    }
+
+    /// Create a baseline which accepts all the given findings.
+    pub fn from_reports<'a>(reports: impl IntoIterator<Item = &'a Report>) -> Self {
+        let mut baseline = Self::default();
+        for r in reports {
+            let known = baseline
+                .known
+                .entry(key(r))
+                .or_insert((0, r.message().to_owned()));
+            known.0 += 1;
+        }
+        baseline
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = BaselineFile {
+            version: VERSION,
+            findings: self
+                .known
+                .iter()
+                .map(|((path, rule, fingerprint), (count, message))| Entry {
+                    path: path.to_owned(),
+                    rule: rule.to_owned(),
+                    fingerprint: fingerprint.to_owned(),
+                    count: *count,
+                    message: message.to_owned(),
+                })
+                .collect(),
+        };
+        let mut content =
+            serde_json::to_string_pretty(&file).map_err(|e| Error::Parse(path.to_owned(), e))?;
+        content.push('\n');
+        std::fs::write(path, content).map_err(|e| Error::Io(path.to_owned(), e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.known.values().map(|(n, _)| n).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all findings which are part of the baseline.
+    ///
+    /// Each entry of the baseline accepts as many findings as it was recorded
+    /// with, additional occurrences of the same finding are kept.
+    pub fn filter(&self, reports: Vec<Report>) -> Vec<Report> {
+        let mut remaining = self
+            .known
+            .iter()
+            .map(|(k, (n, _))| (k, *n))
+            .collect::<BTreeMap<_, _>>();
+        reports
+            .into_iter()
+            .filter(|r| match remaining.get_mut(&key(r)) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    false
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+fn key(report: &Report) -> Key {
+    // Ignore `.` components so that `ssr check` and `ssr check .` agree.
+    let path = report
+        .path()
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<PathBuf>();
+    (
+        path,
+        report.rule().unwrap_or_default().to_owned(),
+        report.fingerprint().unwrap_or_default().to_owned(),
+    )
+}
+
+/// Stable fingerprint of text fragments, independent of whitespace changes.
+///
+/// Uses FNV-1a since the hash must not change between builds or platforms.
+pub(crate) fn fingerprint<'a>(parts: impl IntoIterator<Item = &'a str>) -> String {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = OFFSET;
+    let mut update = |b: u8| {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(PRIME);
+    };
+    for part in parts {
+        for word in part.split_whitespace() {
+            word.bytes().for_each(&mut update);
+            update(b' ');
+        }
+        update(0);
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Language, Rules};
+
+    const RULES: &str = r#"
+[[rule]]
+id = "no-dbg"
+language = "rust"
+query = '((macro_invocation macro: (identifier) @m (#eq? @m "dbg")) @call)'
+message = "leftover dbg!"
+"#;
+
+    fn check(content: &str) -> Vec<Report> {
+        let rules = Rules::parse("ssr.toml", RULES).unwrap();
+        let doc = crate::testing::doc(Language::Rust, content);
+        rules.iter().flat_map(|r| r.check(&doc).unwrap()).collect()
+    }
+
+    fn fingerprints(reports: &[Report]) -> Vec<&str> {
+        reports.iter().filter_map(Report::fingerprint).collect()
+    }
+
+    #[test]
+    fn fingerprint_ignores_position_and_indentation() {
+        let before = check("fn f() {\n    dbg!(1);\n}\n");
+        let after = check("// moved\n\nfn f() {\n        dbg!(1);\n}\n");
+        assert_eq!(fingerprints(&before), fingerprints(&after));
+        assert_ne!(before[0].range(), after[0].range());
+
+        let other = check("fn f() {\n    dbg!(2);\n}\n");
+        assert_ne!(fingerprints(&before), fingerprints(&other));
+    }
+
+    #[test]
+    fn fingerprint_separates_parts() {
+        assert_ne!(fingerprint(["ab", "c"]), fingerprint(["a", "bc"]));
+        assert_eq!(fingerprint(["a  b"]), fingerprint(["a\nb"]));
+    }
+
+    #[test]
+    fn filter_accepts_recorded_count() {
+        let baseline = Baseline::from_reports(&check("fn f() { dbg!(1); }\n"));
+        assert_eq!(baseline.len(), 1);
+
+        // A second identical finding is new, even after lines shifted.
+        let reports = check("\nfn f() { dbg!(1); }\nfn g() { dbg!(1); }\n");
+        assert_eq!(baseline.filter(reports).len(), 1);
+
+        let reports = check("fn f() { dbg!(2); }\n");
+        assert_eq!(baseline.filter(reports).len(), 1);
+        assert!(baseline.filter(check("fn f() {}\n")).is_empty());
+    }
+
+    #[test]
+    fn filter_ignores_current_dir_components() {
+        let baseline = Baseline::from_reports(&check("fn f() { dbg!(1); }\n"));
+        let reports = check("fn f() { dbg!(1); }\n")
+            .into_iter()
+            .map(|r| {
+                Report::new("./a.rs".into(), *r.range(), String::new(), r.severity())
+                    .with_rule(r.rule().unwrap())
+                    .with_fingerprint(r.fingerprint().unwrap())
+            })
+            .collect();
+        assert!(baseline.filter(reports).is_empty());
+    }
+
+    #[test]
+    fn write_and_open() {
+        let dir = crate::testing::TempDir::new("baseline");
+        let path = dir.path().join("baseline.json");
+        let reports = check("fn f() { dbg!(1); dbg!(1); }\n");
+        Baseline::from_reports(&reports).write(&path).unwrap();
+        let baseline = Baseline::open(&path).unwrap();
+        assert_eq!(baseline.len(), 2);
+        assert!(baseline.filter(reports).is_empty());
+
+        let path = dir.path().join("version.json");
+        std::fs::write(&path, r#"{"version": 2, "findings": []}"#).unwrap();
+        assert!(matches!(Baseline::open(&path), Err(Error::Version(_, 2))));
+    }
+}
@@ -1,17 +1,139 @@
-use crate::Language;
+use crate::{Language, Report, Severity};
 use std::{
+    borrow::Cow,
+    ops::Range,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
+const BOM: char = '\u{feff}';
+/// Byte offset of ranges which scripts build from lines and columns, they are
+/// located in the document they are applied to, see [`Change::locate`].
+pub(crate) const UNLOCATED: usize = usize::MAX;
+
 pub struct Document {
     path: PathBuf,
     lang: Language,
+    /// Content without the byte order mark.
     content: String,
+    bom: bool,
+    line_ending: LineEnding,
+    /// Detected once, it is needed for every reindented edit.
+    indent: Indent,
+    /// Read from UTF-16 or Latin-1, the content can not be written back.
+    decoded: bool,
     // parser: tree_sitter::Parser,
     tree: tree_sitter::Tree,
 }
 
+/// Indentation convention of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Indent {
+    Tabs,
+    /// Spaces with the number of spaces per level.
+    Spaces(usize),
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self::Spaces(4)
+    }
+}
+
+impl Indent {
+    /// Detect the indentation used by most indented lines of `text`, the
+    /// number of spaces is the most common increase of indentation.
+    pub fn detect(text: &str) -> Self {
+        let (mut tabs, mut spaces) = (0, 0);
+        let mut steps = [0usize; 9];
+        let mut previous = 0;
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let width = line.len() - line.trim_start_matches(' ').len();
+            if line.starts_with('\t') {
+                tabs += 1;
+            } else if width > 0 {
+                spaces += 1;
+            }
+            if width > previous && width - previous < steps.len() {
+                steps[width - previous] += 1;
+            }
+            previous = width;
+        }
+        let step = (1..steps.len()).rev().max_by_key(|&k| steps[k]);
+        match step {
+            _ if tabs > spaces => Self::Tabs,
+            Some(step) if steps[step] > 0 => Self::Spaces(step),
+            _ => Self::default(),
+        }
+    }
+
+    /// Columns per level, tabs count as four columns.
+    fn width(&self) -> usize {
+        match self {
+            Self::Tabs => 4,
+            Self::Spaces(n) => (*n).max(1),
+        }
+    }
+
+    /// Width of the leading whitespace of `line` in columns.
+    fn columns(&self, line: &str) -> usize {
+        line.chars()
+            .map_while(|c| match c {
+                '\t' => Some(self.width()),
+                ' ' => Some(1),
+                _ => None,
+            })
+            .sum()
+    }
+
+    /// Whitespace for `levels` levels.
+    fn levels(&self, levels: usize) -> String {
+        match self {
+            Self::Tabs => "\t".repeat(levels),
+            Self::Spaces(n) => " ".repeat(levels * n),
+        }
+    }
+}
+
+/// Line ending convention of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Detect the line ending used by most lines of `text`.
+    pub fn detect(text: &str) -> Self {
+        let lines = text.matches('\n').count();
+        let crlf = text.matches("\r\n").count();
+        if crlf * 2 > lines {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+
+    /// Convert all line endings of `text` to this one.
+    pub fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Lf if text.contains("\r\n") => text.replace("\r\n", "\n").into(),
+            Self::CrLf if text.replace("\r\n", "").contains('\n') => {
+                text.replace("\r\n", "\n").replace('\n', "\r\n").into()
+            }
+            _ => text.into(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Match {
     id: u32,
@@ -29,6 +151,17 @@ impl Match {
     pub fn captures(&self) -> impl Iterator<Item = Capture> + '_ {
         self.captures.iter().cloned()
     }
+    /// Smallest range which covers all captures of this match.
+    pub fn range(&self) -> Option<tree_sitter::Range> {
+        let start = self.captures.iter().min_by_key(|c| c.range.start_byte)?;
+        let end = self.captures.iter().max_by_key(|c| c.range.end_byte)?;
+        Some(tree_sitter::Range {
+            start_byte: start.range.start_byte,
+            end_byte: end.range.end_byte,
+            start_point: start.range.start_point,
+            end_point: end.range.end_point,
+        })
+    }
 }
 
 impl rhai::CustomType for Match {
@@ -116,29 +249,86 @@ pub enum Error {
     ),
     #[error("I/O error in {0}: {1}")]
     Io(PathBuf, #[source] std::io::Error),
+    #[error("Skipped {1} file {0}")]
+    Skipped(PathBuf, Skip),
 }
 
+/// Number of bytes at the start of a file which are checked for markers.
+const HEAD: usize = 8 * 1024;
+
+/// Markers of generated files, only searched in the first lines.
+const GENERATED: [&str; 2] = ["@generated", "DO NOT EDIT"];
+const GENERATED_LINES: usize = 20;
+
 impl Document {
     pub fn open<P: AsRef<Path>>(path: P, lang: Language) -> Result<Self> {
         let content = std::fs::read_to_string(path.as_ref()).map_err(|e| {
             let p = path.as_ref().to_owned();
             Error::Io(p, e)
         })?;
+        Self::with_content(path.as_ref().to_owned(), lang, content)
+    }
 
-        let mut parser = tree_sitter::Parser::new();
-        parser.set_language(&lang.language())?;
-        let tree = parser.parse(&content, None).ok_or(Error::ParsingFailed)?;
+    /// Open a document like [`Document::open`], but skip files which are
+    /// binary, generated, too large or not UTF-8 according to `options` with
+    /// [`Error::Skipped`].
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        lang: Language,
+        options: &OpenOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let skipped = |reason| Error::Skipped(path.to_owned(), reason);
+        if let Some(max) = options.max_size {
+            let metadata = std::fs::metadata(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+            if metadata.len() > max {
+                return Err(skipped(Skip::TooLarge));
+            }
+        }
 
-        Ok(Self {
-            path: path.as_ref().to_owned(),
-            lang,
-            content,
-            // parser,
-            tree,
-        })
+        let bytes = std::fs::read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        let utf16 = match bytes.as_slice() {
+            [0xff, 0xfe, rest @ ..] if options.decode => {
+                Some(decode_utf16(rest, u16::from_le_bytes))
+            }
+            [0xfe, 0xff, rest @ ..] if options.decode => {
+                Some(decode_utf16(rest, u16::from_be_bytes))
+            }
+            _ => None,
+        };
+        let (content, decoded) = match utf16 {
+            Some(Some(content)) => (content, true),
+            Some(None) => return Err(skipped(Skip::Encoding)),
+            None if bytes[..bytes.len().min(HEAD)].contains(&0) => {
+                return Err(skipped(Skip::Binary))
+            }
+            None => match String::from_utf8(bytes) {
+                Ok(content) => (content, false),
+                // Every byte is a valid Latin-1 character.
+                Err(e) if options.decode => {
+                    (e.into_bytes().into_iter().map(char::from).collect(), true)
+                }
+                Err(_) => return Err(skipped(Skip::Encoding)),
+            },
+        };
+
+        if !options.generated && is_generated(&content) {
+            return Err(skipped(Skip::Generated));
+        }
+        let doc = Self::with_content(path.to_owned(), lang, content)?;
+        Ok(Self { decoded, ..doc })
     }
 
-    pub fn with_content(path: PathBuf, lang: Language, content: String) -> Result<Self> {
+    /// Create a document from `content`, a leading byte order mark is removed
+    /// and restored by [`Document::file_content`].
+    pub fn with_content(path: PathBuf, lang: Language, mut content: String) -> Result<Self> {
+        let bom = content.starts_with(BOM);
+        if bom {
+            content.drain(..BOM.len_utf8());
+        }
+        let line_ending = LineEnding::detect(&content);
+        let indent = Indent::detect(&content);
+
         let mut parser = tree_sitter::Parser::new();
         parser.set_language(&lang.language())?;
         let tree = parser.parse(&content, None).ok_or(Error::ParsingFailed)?;
@@ -147,6 +337,10 @@ impl Document {
             path,
             lang,
             content,
+            bom,
+            line_ending,
+            indent,
+            decoded: false,
             // parser,
             tree,
         })
@@ -156,10 +350,126 @@ impl Document {
         self.path.as_path()
     }
 
+    pub fn language(&self) -> Language {
+        self.lang
+    }
+
+    /// The same document at another path.
+    pub(crate) fn with_path(self, path: PathBuf) -> Self {
+        Self { path, ..self }
+    }
+
+    /// The range of the bytes `bytes` with their positions.
+    pub(crate) fn range(&self, bytes: Range<usize>) -> tree_sitter::Range {
+        let point = |byte: usize| {
+            let before = &self.content[..byte];
+            let row = before.matches('\n').count();
+            let column = byte - before.rfind('\n').map_or(0, |i| i + 1);
+            tree_sitter::Point { row, column }
+        };
+        tree_sitter::Range {
+            start_byte: bytes.start,
+            end_byte: bytes.end,
+            start_point: point(bytes.start),
+            end_point: point(bytes.end),
+        }
+    }
+
+    /// The range from `start` to `end` with the bytes of their positions,
+    /// `None` if a position is past the end of its line or `end` is before
+    /// `start`.
+    pub(crate) fn locate(
+        &self,
+        start: tree_sitter::Point,
+        end: tree_sitter::Point,
+    ) -> Option<tree_sitter::Range> {
+        let byte = |point: tree_sitter::Point| {
+            let start = match point.row {
+                0 => 0,
+                row => self.content.match_indices('\n').nth(row - 1)?.0 + 1,
+            };
+            let line = self.content[start..].split('\n').next().unwrap_or_default();
+            let valid = point.column <= line.len() && line.is_char_boundary(point.column);
+            valid.then_some(start + point.column)
+        };
+        let (start, end) = (byte(start)?, byte(end)?);
+        (start <= end).then(|| self.range(start..end))
+    }
+
+    pub(crate) fn tree(&self) -> &tree_sitter::Tree {
+        &self.tree
+    }
+
     pub fn content(&self) -> &str {
         self.content.as_str()
     }
 
+    /// Content as it is written to a file, with the byte order mark if the
+    /// document had one.
+    pub fn file_content(&self) -> Cow<'_, str> {
+        if self.bom {
+            format!("{BOM}{}", self.content).into()
+        } else {
+            self.content.as_str().into()
+        }
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Whether the document was decoded from UTF-16 or Latin-1 with
+    /// [`OpenOptions::decode`].
+    pub fn is_decoded(&self) -> bool {
+        self.decoded
+    }
+
+    /// Indentation used by most indented lines.
+    pub fn indent(&self) -> Indent {
+        self.indent
+    }
+
+    /// Indent `text` inserted at `point` to fit in: the common indentation of
+    /// its lines is removed, the remaining indentation is converted to the
+    /// indentation of the document and the second and following lines are
+    /// prefixed with the indentation of the line at `point`. Later lines keep
+    /// their nesting relative to the first line.
+    pub fn reindent(&self, text: &str, point: tree_sitter::Point) -> String {
+        let line = self.content.lines().nth(point.row).unwrap_or_default();
+        let base = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        // Levels are converted from the indentation used by `text`.
+        let (from, to) = (Indent::detect(text), self.indent());
+        let common = text
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| from.columns(l))
+            .min()
+            .unwrap_or(0);
+
+        let mut result = String::new();
+        for (k, line) in text.split('\n').enumerate() {
+            if k > 0 {
+                result.push('\n');
+            }
+            let content = line.trim_start_matches([' ', '\t']);
+            if content.trim().is_empty() {
+                continue;
+            }
+            if k > 0 {
+                result.push_str(base);
+            }
+            let columns = from.columns(line).saturating_sub(common);
+            result.push_str(&to.levels(columns / from.width()));
+            result.push_str(&" ".repeat(columns % from.width()));
+            result.push_str(content);
+        }
+        result
+    }
+
     pub fn lines(&self) -> impl Iterator<Item = String> {
         let vec = self
             .content
@@ -198,42 +508,17 @@ impl Document {
     }
 
     pub fn edit(&self, query: &str, script: &str) -> Result<Self> {
-        let engine = {
-            let mut engine = rhai::Engine::new();
-            engine.build_type::<DocumentEdits>();
-            engine.build_type::<crate::Match>();
-            engine.build_type::<crate::Capture>();
-            engine
-        };
-        let ast = engine
-            .compile(script)
-            .map_err(|e| Error::Compile(e.to_string()))?;
-        let found = self
-            .find(&crate::Query::new(self.lang, query)?)?
-            .collect::<Vec<_>>();
-
-        let edits = DocumentEdits::default();
-        let mut scope = rhai::Scope::new();
-        scope.push("document", edits.clone());
-
-        for m in found {
-            scope.set_value("found", m);
-
-            let _result = engine
-                .eval_ast_with_scope::<rhai::Dynamic>(&mut scope, &ast)
-                .map_err({
-                    let p = self.path.to_owned();
-                    |e| Error::Script(p, e.to_string())
-                })?;
-        }
-        self.apply_edits(edits.changes())
+        let script = crate::Script::new(script)?;
+        let found = self.find(&crate::Query::new(self.lang, query)?)?;
+        let output = script.run(self, found)?;
+        self.apply_edits(output.changes().iter().cloned())
     }
 
-    fn apply_edits(&self, changes: impl Iterator<Item = Change>) -> Result<Self> {
+    pub fn apply_edits(&self, changes: impl IntoIterator<Item = Change>) -> Result<Self> {
         let changes = {
-            let mut e = changes.collect::<Vec<_>>();
+            let mut e = changes.into_iter().collect::<Vec<_>>();
             // Sort edits in *reverse* by edit start position.
-            e.sort_by(|b, a| a.range.start_byte.cmp(&b.range.start_byte));
+            e.sort_by_key(|e| std::cmp::Reverse(e.range.start_byte));
             e
         };
         let mut content = self.content.clone();
@@ -265,7 +550,13 @@ impl Document {
             // self.tree.edit(&input_edit);
             content = {
                 let mut t = content[0..edit.range.start_byte].to_owned();
-                t.push_str(edit.replacement.as_str());
+                let replacement = if edit.reindent {
+                    self.reindent(&edit.replacement, edit.range.start_point)
+                        .into()
+                } else {
+                    Cow::from(edit.replacement.as_str())
+                };
+                t.push_str(&self.line_ending.normalize(&replacement));
                 t.push_str(&content[edit.range.end_byte..]);
                 t
             };
@@ -275,61 +566,437 @@ impl Document {
         //     .parse(&self.content, Some(&self.tree))
         //     .ok_or(Error::ParsingFailed)?;
 
-        Self::with_content(self.path.to_owned(), self.lang, content)
+        let mut doc = Self::with_content(self.path.to_owned(), self.lang, content)?;
+        // Keep the conventions even if the edits removed all lines.
+        doc.bom = self.bom;
+        doc.line_ending = self.line_ending;
+        doc.indent = self.indent;
+        doc.decoded = self.decoded;
+        Ok(doc)
+    }
+
+    pub fn write_tree(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.write_tree_with(out, &TreeOptions::default())
     }
 
-    pub fn write_tree(&self, mut out: &mut impl std::io::Write) -> std::io::Result<()> {
-        let mut cursor = self.tree.walk();
+    pub fn write_tree_with(
+        &self,
+        out: &mut impl std::io::Write,
+        options: &TreeOptions,
+    ) -> std::io::Result<()> {
+        match options.format {
+            TreeFormat::Sexp => self.write_tree_sexp(out, options),
+            TreeFormat::Json => self.write_tree_json(out, options),
+            TreeFormat::Dot => self.write_tree_dot(out, options),
+        }
+    }
+
+    fn write_tree_sexp(
+        &self,
+        mut out: &mut impl std::io::Write,
+        options: &TreeOptions,
+    ) -> std::io::Result<()> {
+        let is_shown =
+            |node: &tree_sitter::Node| node.is_named() || node.is_missing() || options.anonymous;
+
         let mut needs_newline = false;
-        let mut indent_level = 0;
-        let mut did_visit_children = false;
-        loop {
-            let node = cursor.node();
-            let is_named = node.is_named();
-            if did_visit_children {
-                if is_named {
-                    out.write_all(b")")?;
-                    needs_newline = true;
+        for (root_field, root) in self.tree_roots(options) {
+            let mut cursor = root.walk();
+            let mut indent_level = 0;
+            let mut did_visit_children = false;
+            loop {
+                let node = cursor.node();
+                let is_shown = is_shown(&node);
+                if did_visit_children {
+                    if is_shown {
+                        out.write_all(b")")?;
+                        needs_newline = true;
+                    }
+                    if cursor.goto_next_sibling() {
+                        did_visit_children = false;
+                    } else if cursor.goto_parent() {
+                        did_visit_children = true;
+                        indent_level -= 1;
+                    } else {
+                        break;
+                    }
+                } else {
+                    if is_shown {
+                        if needs_newline {
+                            out.write_all(b"\n")?;
+                        }
+                        for _ in 0..indent_level {
+                            out.write_all(b"  ")?;
+                        }
+                        if let Some(field_name) = cursor
+                            .field_name()
+                            .or(root_field.filter(|_| indent_level == 0))
+                        {
+                            write!(&mut out, "{field_name}: ")?;
+                        }
+                        self.write_node(out, &node, options)?;
+                        needs_newline = true;
+                    }
+                    let descend = options.depth.is_none_or(|d| indent_level < d);
+                    if descend && cursor.goto_first_child() {
+                        did_visit_children = false;
+                        indent_level += 1;
+                    } else {
+                        did_visit_children = true;
+                    }
                 }
-                if cursor.goto_next_sibling() {
-                    did_visit_children = false;
-                } else if cursor.goto_parent() {
-                    did_visit_children = true;
-                    indent_level -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_tree_json(
+        &self,
+        out: &mut impl std::io::Write,
+        options: &TreeOptions,
+    ) -> std::io::Result<()> {
+        let nodes = self.tree_nodes(options);
+        let mut roots = Vec::new();
+        let mut children = vec![Vec::new(); nodes.len()];
+        for (k, n) in nodes.iter().enumerate() {
+            match n.parent {
+                Some(parent) => children[parent].push(k),
+                None => roots.push(k),
+            }
+        }
+        fn to_json(
+            doc: &Document,
+            options: &TreeOptions,
+            nodes: &[TreeNode],
+            children: &[Vec<usize>],
+            k: usize,
+        ) -> serde_json::Value {
+            let TreeNode { field, node, .. } = &nodes[k];
+            let point = |p: tree_sitter::Point, byte: usize| serde_json::json!({ "row": p.row, "column": p.column, "byte": byte });
+            let mut value = serde_json::json!({
+                "kind": node.kind(),
+                "field": field,
+                "named": node.is_named(),
+                "error": node.is_error(),
+                "missing": node.is_missing(),
+                "start": point(node.start_position(), node.start_byte()),
+                "end": point(node.end_position(), node.end_byte()),
+            });
+            if options.text && node.child_count() == 0 {
+                let text = node.utf8_text(doc.content.as_bytes()).unwrap_or_default();
+                value["text"] = text.into();
+            }
+            value["children"] = children[k]
+                .iter()
+                .map(|&c| to_json(doc, options, nodes, children, c))
+                .collect();
+            value
+        }
+        let mut roots = roots
+            .into_iter()
+            .map(|k| to_json(self, options, &nodes, &children, k))
+            .collect::<Vec<_>>();
+        // A single tree unless `--field` selects any number of subtrees.
+        if options.field.is_some() {
+            serde_json::to_writer_pretty(&mut *out, &roots)?;
+        } else if let Some(root) = roots.pop() {
+            serde_json::to_writer_pretty(&mut *out, &root)?;
+        }
+        Ok(())
+    }
+
+    /// Write the tree as a Graphviz DOT graph.
+    fn write_tree_dot(
+        &self,
+        mut out: &mut impl std::io::Write,
+        options: &TreeOptions,
+    ) -> std::io::Result<()> {
+        writeln!(&mut out, "digraph tree {{")?;
+        writeln!(&mut out, "  node [shape=box, fontname=monospace];")?;
+        for (k, n) in self.tree_nodes(options).iter().enumerate() {
+            let node = &n.node;
+            let start = node.start_position();
+            let end = node.end_position();
+            let mut label = vec![
+                node.kind().to_owned(),
+                format!(
+                    "[{}, {}] - [{}, {}]",
+                    start.row, start.column, end.row, end.column
+                ),
+            ];
+            if options.bytes {
+                label.push(format!("[{}..{}]", node.start_byte(), node.end_byte()));
+            }
+            if options.text && node.child_count() == 0 && node.is_named() {
+                let text = node.utf8_text(self.content.as_bytes()).unwrap_or_default();
+                label.push(format!("{text:?}"));
+            }
+            let label = label
+                .iter()
+                .map(|l| l.replace('\\', "\\\\").replace('"', "\\\""))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            let mut attrs = format!("label=\"{label}\"");
+            if !node.is_named() {
+                attrs.push_str(", shape=ellipse");
+            }
+            if node.is_error() || node.is_missing() {
+                attrs.push_str(", color=red, fontcolor=red");
+            }
+            if node.is_missing() {
+                attrs.push_str(", style=dashed");
+            }
+            writeln!(&mut out, "  n{k} [{attrs}];")?;
+            if let Some(parent) = n.parent {
+                match n.field {
+                    Some(field) => writeln!(&mut out, "  n{parent} -> n{k} [label={field:?}];")?,
+                    None => writeln!(&mut out, "  n{parent} -> n{k};")?,
+                }
+            }
+        }
+        write!(&mut out, "}}")
+    }
+
+    /// All nodes selected by `options` in pre-order, each with the index of
+    /// its parent.
+    fn tree_nodes(&self, options: &TreeOptions) -> Vec<TreeNode<'_>> {
+        let mut nodes = Vec::new();
+        for (root_field, root) in self.tree_roots(options) {
+            let mut parents: Vec<Option<usize>> = vec![None];
+            let mut cursor = root.walk();
+            'walk: loop {
+                let node = cursor.node();
+                let parent = *parents.last().unwrap_or(&None);
+                let index = if node.is_named() || node.is_missing() || options.anonymous {
+                    nodes.push(TreeNode {
+                        parent,
+                        field: cursor
+                            .field_name()
+                            .or(root_field.filter(|_| parents.len() == 1)),
+                        node,
+                    });
+                    Some(nodes.len() - 1)
                 } else {
-                    break;
+                    parent
+                };
+                let descend = options.depth.is_none_or(|d| parents.len() <= d);
+                if descend && cursor.goto_first_child() {
+                    parents.push(index);
+                    continue;
                 }
-            } else {
-                if is_named {
-                    if needs_newline {
-                        out.write_all(b"\n")?;
-                    }
-                    for _ in 0..indent_level {
-                        out.write_all(b"  ")?;
+                loop {
+                    if cursor.goto_next_sibling() {
+                        break;
                     }
-                    let start = node.start_position();
-                    let end = node.end_position();
-                    if let Some(field_name) = cursor.field_name() {
-                        write!(&mut out, "{field_name}: ")?;
+                    if parents.len() == 1 || !cursor.goto_parent() {
+                        break 'walk;
                     }
-                    write!(
-                        &mut out,
-                        "({} [{}, {}] - [{}, {}]",
-                        node.kind(),
-                        start.row,
-                        start.column,
-                        end.row,
-                        end.column
-                    )?;
-                    needs_newline = true;
+                    parents.pop();
                 }
-                if cursor.goto_first_child() {
-                    did_visit_children = false;
-                    indent_level += 1;
-                } else {
-                    did_visit_children = true;
+            }
+        }
+        nodes
+    }
+
+    /// The subtrees to write with the field they are in: the smallest one
+    /// covering `options.range` or the whole tree, and below that every node in
+    /// `options.field`.
+    fn tree_roots(
+        &self,
+        options: &TreeOptions,
+    ) -> Vec<(Option<&'static str>, tree_sitter::Node<'_>)> {
+        let root = self.tree.root_node();
+        let root = match options.range {
+            Some((start, end)) if options.anonymous => root.descendant_for_point_range(start, end),
+            Some((start, end)) => root.named_descendant_for_point_range(start, end),
+            None => Some(root),
+        };
+        let Some(root) = root else {
+            return Vec::new();
+        };
+        let Some(field) = &options.field else {
+            return vec![(None, root)];
+        };
+
+        // Nodes in the field with everything below them, nested ones are part
+        // of the outer subtree.
+        let mut roots = Vec::new();
+        let mut cursor = root.walk();
+        loop {
+            let name = cursor.field_name().filter(|name| name == field);
+            if name.is_some() {
+                roots.push((name, cursor.node()));
+            } else if cursor.goto_first_child() {
+                continue;
+            }
+            loop {
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+                if !cursor.goto_parent() {
+                    return roots;
+                }
+            }
+        }
+    }
+
+    /// Write a single node without its children and without closing it.
+    fn write_node(
+        &self,
+        mut out: &mut impl std::io::Write,
+        node: &tree_sitter::Node,
+        options: &TreeOptions,
+    ) -> std::io::Result<()> {
+        let mut kind = if node.is_named() {
+            node.kind().to_owned()
+        } else {
+            format!("{:?}", node.kind())
+        };
+        if node.is_missing() {
+            kind = format!("MISSING {kind}");
+        }
+        if options.color && (node.is_error() || node.is_missing()) {
+            kind = format!("\x1b[1;31m{kind}\x1b[0m");
+        }
+        let start = node.start_position();
+        let end = node.end_position();
+        write!(
+            &mut out,
+            "({kind} [{}, {}] - [{}, {}]",
+            start.row, start.column, end.row, end.column
+        )?;
+        if options.bytes {
+            write!(&mut out, " [{}..{}]", node.start_byte(), node.end_byte())?;
+        }
+        if options.text && node.child_count() == 0 {
+            let text = node.utf8_text(self.content.as_bytes()).unwrap_or_default();
+            write!(&mut out, " {text:?}")?;
+        }
+        Ok(())
+    }
+
+    /// Suggest a query which matches the smallest named node covering the range
+    /// from `start` to `end`.
+    ///
+    /// Fields are preserved, named leaves are captured and their text is pinned
+    /// with `#eq?` predicates. Comments and other extra nodes are skipped.
+    pub fn suggest_query(
+        &self,
+        start: tree_sitter::Point,
+        end: tree_sitter::Point,
+    ) -> Option<String> {
+        let node = self
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(start, end)?;
+        let mut cursor = node.walk();
+        let mut pattern = String::new();
+        let mut predicates = Vec::new();
+        let mut names = std::collections::HashMap::new();
+        self.suggest_node(&mut cursor, 0, &mut pattern, &mut predicates, &mut names);
+        if predicates.is_empty() {
+            return Some(pattern);
+        }
+
+        let mut query = String::from("(\n");
+        for line in pattern.lines() {
+            query.push_str("  ");
+            query.push_str(line);
+            query.push('\n');
+        }
+        for (name, text) in predicates {
+            query.push_str(&format!("  (#eq? @{name} {})\n", quote(&text)));
+        }
+        query.pop();
+        query.push(')');
+        Some(query)
+    }
+
+    fn suggest_node(
+        &self,
+        cursor: &mut tree_sitter::TreeCursor,
+        indent_level: usize,
+        pattern: &mut String,
+        predicates: &mut Vec<(String, String)>,
+        names: &mut std::collections::HashMap<&'static str, usize>,
+    ) {
+        let node = cursor.node();
+        for _ in 0..indent_level {
+            pattern.push_str("  ");
+        }
+        if let Some(field_name) = cursor.field_name() {
+            pattern.push_str(field_name);
+            pattern.push_str(": ");
+        }
+        if node.named_child_count() == 0 && !node.is_error() {
+            let n = names.entry(node.kind()).or_default();
+            *n += 1;
+            let name = match *n {
+                1 => node.kind().to_owned(),
+                n => format!("{}_{n}", node.kind()),
+            };
+            pattern.push_str(&format!("({}) @{name}", node.kind()));
+            let text = node.utf8_text(self.content.as_bytes()).unwrap_or_default();
+            predicates.push((name, text.to_owned()));
+            return;
+        }
+        pattern.push('(');
+        pattern.push_str(node.kind());
+        if cursor.goto_first_child() {
+            loop {
+                let child = cursor.node();
+                if child.is_named() && !child.is_extra() && !child.is_missing() {
+                    pattern.push('\n');
+                    self.suggest_node(cursor, indent_level + 1, pattern, predicates, names);
                 }
+                if !cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+            cursor.goto_parent();
+        }
+        pattern.push(')');
+    }
+
+    /// Write the smallest named node at `point` and all its ancestors in the
+    /// same layout as [`Document::write_tree`].
+    pub fn write_ancestors(
+        &self,
+        mut out: &mut impl std::io::Write,
+        point: tree_sitter::Point,
+    ) -> std::io::Result<()> {
+        let Some(node) = self
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)
+        else {
+            return Ok(());
+        };
+        let mut path = vec![node];
+        while let Some(parent) = path.last().and_then(|n| n.parent()) {
+            path.push(parent);
+        }
+        path.reverse();
+
+        for (indent_level, node) in path.iter().enumerate() {
+            if indent_level > 0 {
+                out.write_all(b"\n")?;
+            }
+            for _ in 0..indent_level {
+                out.write_all(b"  ")?;
             }
+            let field_name = node.parent().and_then(|parent| {
+                let mut cursor = parent.walk();
+                let k = parent.children(&mut cursor).position(|c| c == *node)?;
+                parent.field_name_for_child(k as u32)
+            });
+            if let Some(field_name) = field_name {
+                write!(&mut out, "{field_name}: ")?;
+            }
+            self.write_node(out, node, &TreeOptions::default())?;
+        }
+        for _ in path.iter() {
+            out.write_all(b")")?;
         }
         Ok(())
     }
@@ -343,6 +1010,158 @@ impl Document {
     }
 }
 
+/// Quote `text` as a string literal of the query language.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct TreeNode<'tree> {
+    parent: Option<usize>,
+    field: Option<&'static str>,
+    node: tree_sitter::Node<'tree>,
+}
+
+/// Options for [`Document::open_with`].
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    /// Skip files larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Also open files marked as generated, e.g. with `@generated`.
+    pub generated: bool,
+    /// Decode files with a UTF-16 byte order mark or which are not valid
+    /// UTF-8 as Latin-1 instead of skipping them.
+    pub decode: bool,
+}
+
+/// Reason why [`Document::open_with`] skipped a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Skip {
+    Binary,
+    Generated,
+    TooLarge,
+    Encoding,
+}
+
+impl Skip {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::Generated => "generated",
+            Self::TooLarge => "too large",
+            Self::Encoding => "non UTF-8",
+        }
+    }
+}
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Decode UTF-16 after the byte order mark, `None` if it is invalid.
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    let units = bytes
+        .chunks(2)
+        .map(|c| unit([c[0], *c.get(1).unwrap_or(&0)]));
+    char::decode_utf16(units)
+        .collect::<std::result::Result<_, _>>()
+        .ok()
+}
+
+fn is_generated(content: &str) -> bool {
+    content
+        .lines()
+        .take(GENERATED_LINES)
+        .any(|line| GENERATED.iter().any(|marker| line.contains(marker)))
+}
+
+/// Options for [`Document::write_tree_with`].
+#[derive(Debug, Clone, Default)]
+pub struct TreeOptions {
+    /// Include anonymous nodes, e.g. punctuation and keywords.
+    pub anonymous: bool,
+    /// Include the byte range of each node.
+    pub bytes: bool,
+    /// Include the text of leaf nodes.
+    pub text: bool,
+    /// Only descend this many levels.
+    pub depth: Option<usize>,
+    /// Only write the smallest subtree which covers this range.
+    pub range: Option<(tree_sitter::Point, tree_sitter::Point)>,
+    /// Only write the subtrees of nodes in this field.
+    pub field: Option<String>,
+    /// Highlight `ERROR` and `MISSING` nodes with ANSI colors.
+    pub color: bool,
+    pub format: TreeFormat,
+}
+
+/// Output format of [`Document::write_tree_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TreeFormat {
+    /// Indented S-expressions.
+    #[default]
+    Sexp,
+    /// Nested JSON objects.
+    Json,
+    /// Graphviz DOT graph.
+    Dot,
+}
+
+#[derive(Debug)]
+pub struct TreeFormatError;
+
+impl TreeFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sexp => "sexp",
+            Self::Json => "json",
+            Self::Dot => "dot",
+        }
+    }
+}
+
+impl std::fmt::Display for TreeFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid tree format")
+    }
+}
+
+impl std::error::Error for TreeFormatError {}
+
+impl std::fmt::Display for TreeFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for TreeFormat {
+    type Err = TreeFormatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let ret = match s.as_str() {
+            "sexp" => Self::Sexp,
+            "json" => Self::Json,
+            "dot" => Self::Dot,
+            _ => return Err(TreeFormatError),
+        };
+        Ok(ret)
+    }
+}
+
 pub struct Patch<'old, 'new> {
     old: &'old Document,
     new: &'new Document,
@@ -356,13 +1175,24 @@ impl<'old, 'new> Patch<'old, 'new> {
     pub fn is_changed(&self) -> bool {
         !self.is_same()
     }
+    /// Zero based rows of the new document which were inserted or replaced.
+    pub fn changed_lines(&self) -> Vec<Range<usize>> {
+        similar::TextDiff::from_lines(&self.old.content, &self.new.content)
+            .ops()
+            .iter()
+            .filter(|op| op.tag() != similar::DiffTag::Equal)
+            .map(|op| op.new_range())
+            .filter(|r| !r.is_empty())
+            .collect()
+    }
 }
 
 impl<'old, 'new> std::fmt::Display for Patch<'old, 'new> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let a = format!("a/{}", self.old.path.display());
         let b = format!("b/{}", self.new.path.display());
-        similar::TextDiff::from_lines(self.old.content.as_str(), self.new.content.as_str())
+        let (old, new) = (self.old.file_content(), self.new.file_content());
+        similar::TextDiff::from_lines(old.as_ref(), new.as_ref())
             .unified_diff()
             .context_radius(5)
             .header(a.as_str(), b.as_str())
@@ -370,21 +1200,98 @@ impl<'old, 'new> std::fmt::Display for Patch<'old, 'new> {
     }
 }
 
-#[derive(Debug, Clone)]
-struct Change {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
     range: tree_sitter::Range,
     replacement: String,
+    reindent: bool,
+}
+
+impl Change {
+    pub(crate) fn new(range: tree_sitter::Range, replacement: String) -> Self {
+        Self {
+            range,
+            replacement,
+            reindent: false,
+        }
+    }
+    pub fn range(&self) -> &tree_sitter::Range {
+        &self.range
+    }
+    pub fn replacement(&self) -> &str {
+        self.replacement.as_str()
+    }
+    /// Indent the second and following lines of the replacement like the line
+    /// at the start of the range, see [`Document::reindent`].
+    pub fn with_reindent(mut self, reindent: bool) -> Self {
+        self.reindent = reindent;
+        self
+    }
+    pub fn is_reindent(&self) -> bool {
+        self.reindent
+    }
+    /// Look up the bytes of a range built from lines and columns in `doc`,
+    /// `None` if it is not in the document.
+    pub(crate) fn locate(mut self, doc: &Document) -> Option<Self> {
+        if self.range.start_byte == UNLOCATED {
+            self.range = doc.locate(self.range.start_point, self.range.end_point)?;
+        }
+        Some(self)
+    }
 }
 
-#[derive(Debug, Default, Clone)]
-struct DocumentEdits {
+#[derive(Debug, Clone)]
+pub(crate) struct DocumentEdits {
+    path: PathBuf,
     edits: Arc<Mutex<Vec<Change>>>,
+    reports: Arc<Mutex<Vec<Report>>>,
+    imports: Arc<Mutex<Vec<crate::import::Request>>>,
 }
 
 impl DocumentEdits {
-    fn changes(self) -> impl Iterator<Item = Change> {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            edits: Default::default(),
+            reports: Default::default(),
+            imports: Default::default(),
+        }
+    }
+
+    pub(crate) fn changes(&self) -> Vec<Change> {
         let mut e = self.edits.lock().unwrap();
-        std::mem::take(&mut *e).into_iter()
+        std::mem::take(&mut *e)
+    }
+
+    pub(crate) fn reports(&self) -> Vec<Report> {
+        let mut r = self.reports.lock().unwrap();
+        std::mem::take(&mut *r)
+    }
+
+    pub(crate) fn imports(&self) -> Vec<crate::import::Request> {
+        let mut i = self.imports.lock().unwrap();
+        std::mem::take(&mut *i)
+    }
+
+    fn import(&mut self, request: crate::import::Request) {
+        let mut imports = self.imports.lock().unwrap();
+        if !imports.contains(&request) {
+            imports.push(request);
+        }
+    }
+
+    fn report(
+        &mut self,
+        range: tree_sitter::Range,
+        message: String,
+        severity: &str,
+    ) -> std::result::Result<(), Box<rhai::EvalAltResult>> {
+        let severity = severity
+            .parse::<Severity>()
+            .map_err(|e| format!("{e}: `{severity}`"))?;
+        let report = Report::new(self.path.to_owned(), range, message, severity);
+        self.reports.lock().unwrap().push(report);
+        Ok(())
     }
 }
 
@@ -392,11 +1299,199 @@ impl rhai::CustomType for DocumentEdits {
     fn build(mut builder: rhai::TypeBuilder<Self>) {
         builder
             .with_name("Document")
+            .with_get("path", |this: &mut Self| this.path.display().to_string())
             .with_fn("edit", |this: &mut Self, range, replacement| {
-                this.edits
-                    .lock()
-                    .unwrap()
-                    .push(Change { range, replacement });
+                this.edits.lock().unwrap().push(Change {
+                    range,
+                    replacement,
+                    reindent: false,
+                });
+            })
+            .with_fn("edit_indented", |this: &mut Self, range, replacement| {
+                this.edits.lock().unwrap().push(Change {
+                    range,
+                    replacement,
+                    reindent: true,
+                });
+            })
+            .with_fn("ensure_import", |this: &mut Self, import: String| {
+                this.import(crate::import::Request::Ensure(import))
+            })
+            .with_fn("remove_unused_import", |this: &mut Self, import: String| {
+                this.import(crate::import::Request::RemoveUnused(import))
+            })
+            .with_fn(
+                "report",
+                |this: &mut Self, range, message: String, severity: &str| {
+                    this.report(range, message, severity)
+                },
+            )
+            .with_fn("report", |this: &mut Self, range, message: String| {
+                this.report(range, message, Severity::Warning.as_str())
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn generated_markers_in_first_lines() {
+        assert!(is_generated("// @generated by build.rs\nfn main() {}\n"));
+        assert!(is_generated("# Code generated by protoc. DO NOT EDIT.\n"));
+        assert!(!is_generated("fn main() {}\n"));
+        assert!(!is_generated("// do not edit\n"));
+
+        let late = format!("{}// @generated\n", "\n".repeat(GENERATED_LINES));
+        assert!(!is_generated(&late));
+    }
+
+    #[test]
+    fn json_tree_text() {
+        let doc = testing::doc(Language::Rust, "fn f() {}\n");
+        let json = |text| {
+            let options = TreeOptions {
+                text,
+                format: TreeFormat::Json,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            doc.write_tree_with(&mut out, &options).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&out).unwrap()
+        };
+        let name = |tree: &serde_json::Value| tree["children"][0]["children"][0].clone();
+        assert_eq!(name(&json(false))["kind"], "identifier");
+        assert_eq!(name(&json(false)).get("text"), None);
+        assert_eq!(name(&json(true))["text"], "f");
+    }
+
+    /// The query suggested for the node from `start` to `end` matches once,
+    /// with all captures in that node.
+    fn assert_suggested(doc: &Document, start: (usize, usize), end: (usize, usize)) -> String {
+        let point = |(row, column)| tree_sitter::Point { row, column };
+        let source = doc.suggest_query(point(start), point(end)).unwrap();
+        let query =
+            crate::Query::new(doc.language(), &source).unwrap_or_else(|e| panic!("{e}: {source}"));
+        let matches = doc.find(&query).unwrap().collect::<Vec<_>>();
+        assert_eq!(matches.len(), 1, "{source}");
+        let range = matches[0].range().unwrap();
+        assert!(point(start) <= range.start_point && range.end_point <= point(end));
+        source
+    }
+
+    #[test]
+    fn suggest_query_matches_node() {
+        let doc = testing::doc(
+            Language::Rust,
+            "fn f() {\n    let x = g(1, /* c */ \"a\\\"b\");\n    let y = g(1, \"c\");\n}\n",
+        );
+        let query = assert_suggested(&doc, (1, 4), (1, 31));
+        assert!(query.contains("value: (call_expression"), "{query}");
+        assert!(!query.contains("block_comment"), "{query}");
+
+        let content = "def f():\n    return g(x, y=x)\n\ng(x, y=z)\n";
+        let doc = testing::doc(Language::Python, content);
+        let query = assert_suggested(&doc, (1, 11), (1, 20));
+        assert!(query.contains("(#eq? @identifier_4 \"x\")"), "{query}");
+    }
+
+    #[test]
+    fn detect_indent() {
+        assert_eq!(
+            Indent::detect("fn f() {\n  if x {\n    y\n  }\n}\n"),
+            Indent::Spaces(2)
+        );
+        assert_eq!(
+            Indent::detect("a\n    b\n        c\n    d\n"),
+            Indent::Spaces(4)
+        );
+        assert_eq!(Indent::detect("a\n\tb\n\t\tc\n"), Indent::Tabs);
+        assert_eq!(Indent::detect("a\nb\n"), Indent::default());
+    }
+
+    #[test]
+    fn reindent_to_document_indent() {
+        let doc = testing::doc(Language::Rust, "fn f() {\n\tif x {\n\t\ty();\n\t}\n}\n");
+        assert_eq!(doc.indent(), Indent::Tabs);
+        let point = tree_sitter::Point { row: 2, column: 2 };
+        assert_eq!(
+            doc.reindent("if y {\n    z();\n}", point),
+            "if y {\n\t\t\tz();\n\t\t}"
+        );
+        assert_eq!(doc.reindent("  z();", point), "z();");
+    }
+
+    #[test]
+    fn reindent_relative_to_first_line() {
+        let doc = testing::doc(Language::Rust, "fn f() {\n    g();\n}\n");
+        let point = tree_sitter::Point { row: 1, column: 4 };
+        let text = "if x {\n    y();\n}";
+        assert_eq!(doc.reindent(text, point), "if x {\n        y();\n    }");
+    }
+
+    #[test]
+    fn reindent_python_block() {
+        // The example of the README.
+        let content = "def main():\n    setup()\n    run()\n";
+        let doc = testing::doc(Language::Python, content);
+        let query = crate::Query::new(
+            Language::Python,
+            r#"((expression_statement (call function: (identifier) @f (#eq? @f "setup"))) @call)"#,
+        )
+        .unwrap();
+        let script = crate::Script::new(
+            r#"document.edit_indented(found.captures[1].range, "if ENABLED:\n    " + found.captures[1].text)"#,
+        )
+        .unwrap();
+        let output = script.run(&doc, doc.find(&query).unwrap()).unwrap();
+        let doc = doc.apply_edits(output.changes().iter().cloned()).unwrap();
+        assert_eq!(
+            doc.content(),
+            "def main():\n    if ENABLED:\n        setup()\n    run()\n"
+        );
+    }
+
+    #[test]
+    fn normalize_line_endings() {
+        assert_eq!(LineEnding::Lf.normalize("a\r\nb\nc"), "a\nb\nc");
+        assert_eq!(LineEnding::CrLf.normalize("a\r\nb\nc"), "a\r\nb\r\nc");
+        assert!(matches!(LineEnding::Lf.normalize("a\nb"), Cow::Borrowed(_)));
+        assert!(matches!(
+            LineEnding::CrLf.normalize("a\r\nb"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), LineEnding::CrLf);
+        assert_eq!(LineEnding::detect("a\r\nb\nc\n"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn byte_order_mark_round_trip() {
+        let content = "\u{feff}fn main() {}\r\n";
+        let doc = testing::doc(Language::Rust, content);
+        assert!(doc.has_bom());
+        assert_eq!(doc.content(), "fn main() {}\r\n");
+        assert_eq!(doc.file_content(), content);
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+
+        let root = doc.tree.root_node();
+        assert_eq!(root.child(0).unwrap().start_byte(), 0);
+    }
+
+    #[test]
+    fn open_skips_generated_files() {
+        let dir = testing::TempDir::new("generated");
+        let path = dir.path().join("a.rs");
+        std::fs::write(&path, "// @generated\nfn main() {}\n").unwrap();
+
+        let options = OpenOptions::default();
+        let result = Document::open_with(&path, Language::Rust, &options);
+        assert!(matches!(result, Err(Error::Skipped(_, Skip::Generated))));
+        let options = OpenOptions {
+            generated: true,
+            ..options
+        };
+        assert!(Document::open_with(&path, Language::Rust, &options).is_ok());
+    }
+}
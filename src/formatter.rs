@@ -0,0 +1,208 @@
+//
+// External formatters run on rewritten documents.
+//
+use crate::Document;
+use std::{
+    io::Write,
+    ops::Range,
+    process::{Command, Stdio},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid formatter command `{0}`")]
+    Command(String),
+    #[error("Failed to run formatter `{0}`: {1}")]
+    Io(String, #[source] std::io::Error),
+    #[error("Formatter `{0}` failed for {1}: {2}")]
+    Failed(String, String, String),
+    #[error("Formatter `{0}` returned invalid UTF-8")]
+    Encoding(String),
+    #[error(transparent)]
+    Document(#[from] crate::DocumentError),
+}
+
+/// A command which reads a document on stdin and writes it formatted to
+/// stdout.
+///
+/// `{path}` in an argument is replaced by the path of the document. An argument
+/// with `{start}` and `{end}` is repeated for every range of changed lines
+/// (one based and inclusive) to only format those, if the formatter supports
+/// it.
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    command: String,
+    program: String,
+    args: Vec<String>,
+}
+
+impl Formatter {
+    /// Parse a command line, arguments are split at whitespace unless quoted.
+    pub fn parse(command: &str) -> Result<Self> {
+        let mut words = split(command).ok_or_else(|| Error::Command(command.to_owned()))?;
+        if words.is_empty() {
+            return Err(Error::Command(command.to_owned()));
+        }
+        let program = words.remove(0);
+        Ok(Self {
+            command: command.to_owned(),
+            program,
+            args: words,
+        })
+    }
+
+    /// Format `doc`, where `lines` are the zero based rows which changed.
+    pub fn format(&self, doc: &Document, lines: &[Range<usize>]) -> Result<Document> {
+        let path = doc.path().display().to_string();
+        let mut args = Vec::new();
+        for arg in self.args.iter() {
+            let arg = arg.replace("{path}", &path);
+            if arg.contains("{start}") || arg.contains("{end}") {
+                args.extend(lines.iter().map(|r| {
+                    arg.replace("{start}", &(r.start + 1).to_string())
+                        .replace("{end}", &r.end.max(r.start + 1).to_string())
+                }));
+            } else {
+                args.push(arg);
+            }
+        }
+
+        let io = |e| Error::Io(self.command.to_owned(), e);
+        let mut child = Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(io)?;
+        let input = doc.file_content().into_owned();
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // Write from another thread, the formatter may fill stdout first.
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let output = child.wait_with_output().map_err(io)?;
+        match writer.join().expect("writer does not panic") {
+            // The formatter may exit without reading everything, e.g. on errors.
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(io(e)),
+            _ => {}
+        }
+
+        if !output.status.success() {
+            let mut stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            if stderr.is_empty() {
+                stderr = output.status.to_string();
+            }
+            return Err(Error::Failed(self.command.to_owned(), path, stderr));
+        }
+        let content = String::from_utf8(output.stdout)
+            .map_err(|_| Error::Encoding(self.command.to_owned()))?;
+        Ok(Document::with_content(
+            doc.path().to_owned(),
+            doc.language(),
+            content,
+        )?)
+    }
+}
+
+/// Split `command` into words like a shell, without any expansion.
+fn split(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => w.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => w.push(chars.next()?),
+                        c => w.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).push(chars.next()?),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::doc, Language};
+
+    #[test]
+    fn split_words() {
+        let words = |s| split(s).unwrap();
+        assert_eq!(words("  a  b\tc "), ["a", "b", "c"]);
+        assert_eq!(words("a 'b c' \"d e\""), ["a", "b c", "d e"]);
+        assert_eq!(words("--x='{path}'"), ["--x={path}"]);
+        assert_eq!(words(r#""a \"b\" \\" 'c\d'"#), [r#"a "b" \"#, r"c\d"]);
+        assert_eq!(words(r"a\ b ''"), ["a b", ""]);
+        assert!(words("").is_empty());
+        assert_eq!(split("'a"), None);
+        assert_eq!(split("\"a"), None);
+        assert_eq!(split("a\\"), None);
+    }
+
+    #[test]
+    fn parse_command() {
+        let f = Formatter::parse("black -q -").unwrap();
+        assert_eq!(f.program, "black");
+        assert_eq!(f.args, ["-q", "-"]);
+        assert!(matches!(Formatter::parse("  "), Err(Error::Command(_))));
+        assert!(matches!(Formatter::parse("a 'b"), Err(Error::Command(_))));
+    }
+
+    #[test]
+    fn format_with_arguments() {
+        let f =
+            Formatter::parse("sh -c 'cat; echo \"# $*\"' sh {path} --lines={start}:{end}").unwrap();
+        let formatted = f
+            .format(&doc(Language::Python, "x = 1\n"), &[0..1, 3..3])
+            .unwrap();
+        assert_eq!(
+            formatted.content(),
+            "x = 1\n# a.py --lines=1:1 --lines=4:4\n"
+        );
+        assert_eq!(formatted.path(), std::path::Path::new("a.py"));
+    }
+
+    #[test]
+    fn failing_formatter() {
+        let f = Formatter::parse("sh -c 'echo bad input >&2; exit 3'").unwrap();
+        match f.format(&doc(Language::Python, "x = 1\n"), &[]) {
+            Err(Error::Failed(_, path, stderr)) => {
+                assert_eq!(path, "a.py");
+                assert_eq!(stderr, "bad input");
+            }
+            r => panic!("expected a failure, got {:?}", r.err()),
+        }
+
+        let f = Formatter::parse("sh -c 'exit 3'").unwrap();
+        match f.format(&doc(Language::Python, "x = 1\n"), &[]) {
+            Err(Error::Failed(_, _, status)) => assert!(status.contains('3'), "{status}"),
+            r => panic!("expected a failure, got {:?}", r.err()),
+        }
+
+        let f = Formatter::parse("ssr-no-such-formatter").unwrap();
+        assert!(matches!(
+            f.format(&doc(Language::Python, ""), &[]),
+            Err(Error::Io(..))
+        ));
+    }
+}
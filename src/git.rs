@@ -0,0 +1,206 @@
+//
+// Files and lines changed according to git.
+//
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to run git: {0}")]
+    Io(#[source] std::io::Error),
+    #[error("git {0} failed: {1}")]
+    Git(String, String),
+    #[error("Invalid hunk header in git diff: {0}")]
+    Hunk(String),
+}
+
+/// Files changed in the working tree or the index, with the changed lines.
+///
+/// Files are keyed by their canonical path, deleted files are omitted.
+#[derive(Debug, Default)]
+pub struct Changes {
+    files: BTreeMap<PathBuf, Vec<Range<usize>>>,
+}
+
+impl Changes {
+    /// Changes of the working tree since the revision `rev`, including
+    /// untracked files which are not ignored.
+    pub fn since(rev: &str) -> Result<Self> {
+        let mut changes = Self::diff(&[rev])?;
+        for path in git(&["ls-files", "--others", "--exclude-standard"])?.lines() {
+            let path = Path::new(path);
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+            // All lines of a new file are changed.
+            changes
+                .files
+                .insert(path, std::iter::once(0..usize::MAX).collect());
+        }
+        Ok(changes)
+    }
+
+    /// Changes staged in the index.
+    pub fn staged() -> Result<Self> {
+        Self::diff(&["--cached"])
+    }
+
+    fn diff(args: &[&str]) -> Result<Self> {
+        let mut diff = vec!["diff", "--unified=0", "--no-color", "--no-ext-diff"];
+        diff.extend(["--no-prefix", "--relative", "--diff-filter=d"]);
+        diff.extend(args);
+        diff.push("--");
+        Self::parse(&git(&diff)?)
+    }
+
+    /// Parse a diff with zero lines of context.
+    fn parse(diff: &str) -> Result<Self> {
+        let mut changes = Self::default();
+        let mut current = None;
+        // Added lines can look like file headers, which only come before the
+        // first hunk of a file.
+        let mut header = false;
+        for line in diff.lines() {
+            if line.starts_with("diff ") {
+                current = None;
+                header = true;
+            } else if let Some(path) = line.strip_prefix("+++ ").filter(|_| header) {
+                let path = Path::new(path);
+                let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+                changes.files.entry(path.clone()).or_default();
+                current = Some(path);
+            } else if let Some(range) = line.strip_prefix("@@ ") {
+                let rows = hunk(range).ok_or_else(|| Error::Hunk(line.to_owned()))?;
+                if let Some(path) = &current {
+                    changes.files.entry(path.clone()).or_default().push(rows);
+                }
+                header = false;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Canonical paths of the changed files.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+
+    /// Check if any line of `range` in the file at `path` was changed.
+    pub fn touches(&self, path: &Path, range: &tree_sitter::Range) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let rows = range.start_point.row..range.end_point.row + 1;
+        self.files.get(&path).is_some_and(|hunks| {
+            hunks
+                .iter()
+                .any(|h| h.start < rows.end && rows.start < h.end)
+        })
+    }
+}
+
+/// Run git with `args` and return its output.
+fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(["-c", "core.quotePath=false"])
+        .args(args)
+        .output()
+        .map_err(Error::Io)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(Error::Git(args.join(" "), stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Zero based rows of the new side of a hunk header `-a,b +c,d @@`.
+fn hunk(header: &str) -> Option<Range<usize>> {
+    let new = header.split(' ').find_map(|s| s.strip_prefix('+'))?;
+    let (start, count) = match new.split_once(',') {
+        Some((start, count)) => (start.parse::<usize>().ok()?, count.parse().ok()?),
+        None => (new.parse().ok()?, 1),
+    };
+    // A hunk without lines only removed lines after `start`, it is the empty
+    // range between that line and the next one.
+    let start = match count {
+        0 => start,
+        _ => start.saturating_sub(1),
+    };
+    Some(start..start + count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: Range<usize>) -> tree_sitter::Range {
+        let point = |row| tree_sitter::Point { row, column: 0 };
+        tree_sitter::Range {
+            start_byte: 0,
+            end_byte: 0,
+            start_point: point(rows.start),
+            end_point: point(rows.end),
+        }
+    }
+
+    #[test]
+    fn hunk_headers() {
+        assert_eq!(hunk("-1,2 +3,4 @@"), Some(2..6));
+        // Counts of one line are omitted.
+        assert_eq!(hunk("-1 +3 @@ fn f() {"), Some(2..3));
+        assert_eq!(hunk("-7,0 +8 @@"), Some(7..8));
+        // Only removed lines.
+        assert_eq!(hunk("-6 +5,0 @@"), Some(5..5));
+        assert_eq!(hunk("-1,3 +0,0 @@"), Some(0..0));
+        assert_eq!(hunk("-1 +x @@"), None);
+        assert_eq!(hunk("-1 @@"), None);
+    }
+
+    #[test]
+    fn parse_diff() {
+        let diff = "\
+diff --git src/a.rs src/a.rs
+index 1..2 100644
+--- src/a.rs
++++ src/a.rs
+@@ -2 +2 @@ fn a() {
+-    old();
++    new();
+@@ -6 +5,0 @@
+-    removed();
+diff --git old.rs sub/renamed.rs
+similarity index 90%
+rename from old.rs
+rename to sub/renamed.rs
+--- old.rs
++++ sub/renamed.rs
+@@ -1,0 +2,2 @@
++++ looks like a header
++--- as well
+";
+        let changes = Changes::parse(diff).unwrap();
+        let files = changes.files().collect::<Vec<_>>();
+        assert_eq!(files, [Path::new("src/a.rs"), Path::new("sub/renamed.rs")]);
+
+        let a = Path::new("src/a.rs");
+        assert!(changes.touches(a, &rows(1..1)));
+        assert!(!changes.touches(a, &rows(0..0)));
+        assert!(!changes.touches(a, &rows(2..3)));
+        // The removed line was between rows 4 and 5.
+        assert!(changes.touches(a, &rows(4..5)));
+        assert!(!changes.touches(a, &rows(4..4)));
+        assert!(!changes.touches(a, &rows(5..5)));
+
+        let renamed = Path::new("sub/renamed.rs");
+        assert!(changes.touches(renamed, &rows(2..2)));
+        assert!(!changes.touches(renamed, &rows(3..3)));
+        assert!(!changes.touches(Path::new("old.rs"), &rows(0..10)));
+
+        assert!(matches!(
+            Changes::parse("@@ -1 +a @@\n"),
+            Err(Error::Hunk(_))
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+//
+// Frequency table of the distinct texts of a capture.
+//
+use crate::{report::csv_field, Capture, Format};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Number of example locations kept per group.
+const EXAMPLES: usize = 3;
+
+/// Captures grouped by their text.
+#[derive(Debug, Default)]
+pub struct Groups {
+    groups: HashMap<String, Group>,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    count: usize,
+    files: BTreeSet<PathBuf>,
+    examples: Vec<(PathBuf, tree_sitter::Point)>,
+}
+
+impl Groups {
+    /// Count `capture` found in the file at `path`.
+    pub fn add(&mut self, path: &Path, capture: &Capture) {
+        let group = self.groups.entry(capture.text().to_owned()).or_default();
+        group.count += 1;
+        group.files.insert(path.to_owned());
+        if group.examples.len() < EXAMPLES {
+            group
+                .examples
+                .push((path.to_owned(), *capture.start_position()));
+        }
+    }
+
+    /// Merge the groups of `other` into these.
+    pub fn extend(&mut self, other: Groups) {
+        for (text, other) in other.groups {
+            let group = self.groups.entry(text).or_default();
+            group.count += other.count;
+            group.files.extend(other.files);
+            let n = EXAMPLES.saturating_sub(group.examples.len());
+            group.examples.extend(other.examples.into_iter().take(n));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Groups sorted by descending count and then by text.
+    fn sorted(&self) -> Vec<(&String, &Group)> {
+        let mut groups = self.groups.iter().collect::<Vec<_>>();
+        groups.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        groups
+    }
+
+    /// Write the table to `out` in the given `format`, SARIF is not supported.
+    pub fn write(&self, mut out: impl Write, format: Format) -> std::io::Result<()> {
+        let groups = self.sorted();
+        match format {
+            Format::Text => {
+                writeln!(out, "{:>7} {:>5}  text", "count", "files")?;
+                for (text, g) in groups {
+                    writeln!(
+                        out,
+                        "{:>7} {:>5}  {}  ({})",
+                        g.count,
+                        g.files.len(),
+                        text.escape_debug(),
+                        g.examples().join(", ")
+                    )?;
+                }
+            }
+            Format::Json => {
+                let groups = groups
+                    .into_iter()
+                    .map(|(text, g)| {
+                        serde_json::json!({
+                            "text": text,
+                            "count": g.count,
+                            "files": g.files.len(),
+                            "examples": g.examples(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut out, &groups)?;
+                writeln!(out)?;
+            }
+            Format::Csv => {
+                writeln!(out, "text,count,files,examples")?;
+                for (text, g) in groups {
+                    writeln!(
+                        out,
+                        "{},{},{},{}",
+                        csv_field(text),
+                        g.count,
+                        g.files.len(),
+                        csv_field(&g.examples().join(" "))
+                    )?;
+                }
+            }
+            Format::Sarif => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "groups can not be written as SARIF",
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Group {
+    /// Example locations as `path:line:column`.
+    fn examples(&self) -> Vec<String> {
+        self.examples
+            .iter()
+            .map(|(path, point)| {
+                format!("{}:{}:{}", path.display(), point.row + 1, point.column + 1)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,1072 @@
+//
+// Adding and removing imports of Rust and Python documents.
+//
+use crate::{Change, Document, Language};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tree_sitter::Node;
+
+/// An import change requested by a script, resolved once the script ran for
+/// all matches of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Request {
+    Ensure(String),
+    RemoveUnused(String),
+}
+
+/// An imported item, e.g. `HashMap` from `std::collections`.
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    /// `std::collections` or `foo.bar`, empty for Python `import foo`.
+    module: String,
+    /// The imported item, `*` for wildcards.
+    item: String,
+    /// The name bound in the document, differs from the item for aliases.
+    name: String,
+    alias: bool,
+}
+
+/// An import of a document with the node importing it.
+struct Import<'t> {
+    spec: Spec,
+    node: Node<'t>,
+    /// The top level statement of the import.
+    statement: Node<'t>,
+    /// Whether the import is re-exported, like `pub use`.
+    public: bool,
+}
+
+/// A Rust `use_list` with the path of the module its items are imported from.
+struct List<'t> {
+    path: String,
+    node: Node<'t>,
+    statement: Node<'t>,
+}
+
+/// How new items are added to an existing import.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Merge {
+    /// Into a list of items or a Python `from` statement.
+    List,
+    /// Next to a single imported item, e.g. `a::B` becomes `a::{B, C}`.
+    Item,
+    /// Into the import of the module itself, `a` becomes `a::{self, C}`.
+    Module,
+}
+
+/// The changes to the import statements: the nodes of removed imports and
+/// the items merged into existing nodes, by node id.
+#[derive(Default)]
+struct Plan {
+    remove: HashSet<usize>,
+    add: HashMap<usize, (Merge, Vec<String>)>,
+}
+
+/// Result of rendering an import statement or a part of it with the changes
+/// of a [`Plan`].
+enum Render {
+    Removed,
+    Unchanged,
+    Text(String),
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Changes to `doc` for the import `requests`, where `edits` are the other
+/// changes of the script.
+///
+/// Each import statement with removed or added items is rendered again as a
+/// whole, so removals and additions in the same statement do not conflict.
+pub(crate) fn changes(
+    doc: &Document,
+    requests: &[Request],
+    edits: &[Change],
+) -> Result<Vec<Change>> {
+    let lang = doc.language();
+    if lang == Language::Bazel {
+        return Err("imports are not supported for bazel".to_owned());
+    }
+    let (imports, lists) = imports(doc);
+    // Statements the script edited itself are left alone.
+    let edited = |node: Node| edits.iter().any(|e| overlaps(e.range(), node));
+    // Usage is checked on the document as the script leaves it.
+    let mut rewritten = None;
+    let mut ensure = Vec::new();
+    let mut plan = Plan::default();
+    for request in requests {
+        match request {
+            Request::Ensure(s) => {
+                for spec in parse(lang, s)? {
+                    let present = imports.iter().any(|i| {
+                        i.spec.module == spec.module
+                            && (i.spec.item == "*"
+                                || i.spec.item == spec.item && i.spec.name == spec.name)
+                    });
+                    if !present && !ensure.contains(&spec) {
+                        ensure.push(spec);
+                    }
+                }
+            }
+            Request::RemoveUnused(s) => {
+                let rewritten = match &mut rewritten {
+                    Some(doc) => doc,
+                    None => rewritten.insert(
+                        doc.apply_edits(edits.iter().cloned())
+                            .map_err(|e| e.to_string())?,
+                    ),
+                };
+                for spec in parse(lang, s)? {
+                    let unused = imports.iter().filter(|i| {
+                        !i.public
+                            && i.spec.module == spec.module
+                            && i.spec.item == spec.item
+                            && (!spec.alias || i.spec.name == spec.name)
+                            && !edited(i.statement)
+                            && !is_used(rewritten, &i.spec.name)
+                            && !(lang == Language::Rust && maybe_trait(doc, &i.spec))
+                    });
+                    plan.remove.extend(unused.map(|i| i.node.id()));
+                }
+            }
+        }
+    }
+
+    let mut modules: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for spec in &ensure {
+        let item = match spec.alias {
+            true => format!("{} as {}", spec.item, spec.name),
+            false => spec.item.to_owned(),
+        };
+        modules.entry(&spec.module).or_default().push(item);
+    }
+    let rust = lang == Language::Rust;
+    let mut lines = Vec::new();
+    for (module, items) in modules {
+        let target = match module {
+            "" => None,
+            _ => merge_target(doc, &imports, &lists, module, &plan, &edited),
+        };
+        match target {
+            Some((node, merge)) => {
+                plan.add.insert(node.id(), (merge, items));
+            }
+            None if module.is_empty() => lines.extend(items.iter().map(|i| match rust {
+                true => format!("use {i};"),
+                false => format!("import {i}"),
+            })),
+            None => lines.push(match (rust, items.as_slice()) {
+                (true, [item]) => format!("use {module}::{item};"),
+                (true, _) => format!("use {module}::{{{}}};", items.join(", ")),
+                (false, _) => format!("from {module} import {}", items.join(", ")),
+            }),
+        }
+    }
+
+    let mut changes = Vec::new();
+    // Lines of removed statements, adjacent ones are joined.
+    let mut removed: Vec<std::ops::Range<usize>> = Vec::new();
+    for node in statements(doc) {
+        if edited(node) {
+            continue;
+        }
+        let (range, render) = match lang {
+            Language::Rust => match node.child_by_field_name("argument") {
+                Some(argument) => (argument.byte_range(), render(doc, argument, &plan)),
+                None => continue,
+            },
+            _ => match render_names(doc, node, &plan) {
+                Some(rendered) => rendered,
+                None => continue,
+            },
+        };
+        match render {
+            Render::Removed => match removed.last_mut() {
+                Some(last) if last.end == line_range(doc, node).start => {
+                    last.end = line_range(doc, node).end
+                }
+                _ => removed.push(line_range(doc, node)),
+            },
+            Render::Unchanged => {}
+            Render::Text(text) => changes.push(Change::new(doc.range(range), text)),
+        }
+    }
+    for range in removed {
+        let range = without_blank_line(doc.content(), range);
+        changes.push(Change::new(doc.range(range), String::new()));
+    }
+    if !lines.is_empty() {
+        let (start, text) = insertion(doc, lines);
+        changes.push(Change::new(doc.range(start..start), text));
+    }
+    Ok(changes)
+}
+
+/// Parse `use a::b::C [as D]` (the `use` and `;` are optional) for Rust, or
+/// `from m import a [as b], c` and `import m [as n]` for Python.
+fn parse(lang: Language, import: &str) -> Result<Vec<Spec>> {
+    let invalid = || format!("invalid import `{import}`");
+    let import = import.trim();
+    let split_alias = |s: &str| {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some(item), None, None, None) => Some((item.to_owned(), None)),
+            (Some(item), Some("as"), Some(alias), None) => {
+                Some((item.to_owned(), Some(alias.to_owned())))
+            }
+            _ => None,
+        }
+    };
+    match lang {
+        Language::Rust => {
+            let import = import.strip_prefix("use ").unwrap_or(import);
+            let import = import.strip_suffix(';').unwrap_or(import);
+            let (path, alias) = split_alias(import).ok_or_else(invalid)?;
+            let (module, item) = path.rsplit_once("::").unwrap_or(("", &path));
+            if item.is_empty() || path.contains(['{', '}']) {
+                return Err(invalid());
+            }
+            Ok(vec![Spec {
+                module: module.to_owned(),
+                item: item.to_owned(),
+                name: alias.clone().unwrap_or_else(|| item.to_owned()),
+                alias: alias.is_some(),
+            }])
+        }
+        Language::Python | Language::Bazel => {
+            let (module, names) = if let Some(rest) = import.strip_prefix("from ") {
+                let (module, names) = rest.split_once(" import ").ok_or_else(invalid)?;
+                (module.trim(), names)
+            } else if let Some(names) = import.strip_prefix("import ") {
+                ("", names)
+            } else {
+                return Err(invalid());
+            };
+            let names = names.trim().trim_start_matches('(').trim_end_matches(')');
+            names
+                .split(',')
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .map(|n| {
+                    let (item, alias) = split_alias(n).ok_or_else(invalid)?;
+                    let name = match &alias {
+                        Some(alias) => alias.to_owned(),
+                        // `import a.b` binds `a`.
+                        None if module.is_empty() => {
+                            item.split('.').next().unwrap_or_default().to_owned()
+                        }
+                        None => item.to_owned(),
+                    };
+                    Ok(Spec {
+                        module: module.to_owned(),
+                        item,
+                        name,
+                        alias: alias.is_some(),
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// The top level import statements of `doc`.
+fn statements(doc: &Document) -> Vec<Node<'_>> {
+    let root = doc.tree().root_node();
+    let mut cursor = root.walk();
+    let kinds: &[&str] = match doc.language() {
+        Language::Rust => &["use_declaration"],
+        Language::Python | Language::Bazel => &["import_statement", "import_from_statement"],
+    };
+    root.named_children(&mut cursor)
+        .filter(|n| kinds.contains(&n.kind()))
+        .collect()
+}
+
+/// All top level imports of `doc`, and the Rust lists of imported items.
+fn imports(doc: &Document) -> (Vec<Import<'_>>, Vec<List<'_>>) {
+    let mut imports = Vec::new();
+    let mut lists = Vec::new();
+    for statement in statements(doc) {
+        match doc.language() {
+            Language::Rust => {
+                let public = named_children(statement).any(|c| c.kind() == "visibility_modifier");
+                if let Some(argument) = statement.child_by_field_name("argument") {
+                    let mut tree = UseTree {
+                        doc,
+                        statement,
+                        public,
+                        imports: &mut imports,
+                        lists: &mut lists,
+                    };
+                    tree.flatten(argument, "");
+                }
+            }
+            Language::Python | Language::Bazel => {
+                let module = statement
+                    .child_by_field_name("module_name")
+                    .map(|m| text(doc, m))
+                    .unwrap_or_default();
+                let wildcard = named_children(statement).filter(|c| c.kind() == "wildcard_import");
+                for name in names(statement).chain(wildcard) {
+                    let (item, alias) = match name.kind() {
+                        "dotted_name" => (text(doc, name), None),
+                        "aliased_import" => (
+                            name.child_by_field_name("name")
+                                .map(|n| text(doc, n))
+                                .unwrap_or_default(),
+                            name.child_by_field_name("alias").map(|n| text(doc, n)),
+                        ),
+                        _ => ("*".to_owned(), None),
+                    };
+                    let name_text = match &alias {
+                        Some(alias) => alias.to_owned(),
+                        None if module.is_empty() => {
+                            item.split('.').next().unwrap_or_default().to_owned()
+                        }
+                        None => item.to_owned(),
+                    };
+                    imports.push(Import {
+                        spec: Spec {
+                            module: module.to_owned(),
+                            item,
+                            name: name_text,
+                            alias: alias.is_some(),
+                        },
+                        node: name,
+                        statement,
+                        public: false,
+                    });
+                }
+            }
+        }
+    }
+    (imports, lists)
+}
+
+/// Collects the imports and lists of a Rust use tree.
+struct UseTree<'a, 't> {
+    doc: &'t Document,
+    statement: Node<'t>,
+    public: bool,
+    imports: &'a mut Vec<Import<'t>>,
+    lists: &'a mut Vec<List<'t>>,
+}
+
+impl<'t> UseTree<'_, 't> {
+    /// Collect the imports of `node` below `prefix`.
+    fn flatten(&mut self, node: Node<'t>, prefix: &str) {
+        let join = |path: &str| match (prefix, path) {
+            ("", path) => path.to_owned(),
+            (prefix, "") => prefix.to_owned(),
+            (prefix, path) => format!("{prefix}::{path}"),
+        };
+        let path = |node: Node| {
+            node.child_by_field_name("path")
+                .map(|p| text(self.doc, p))
+                .unwrap_or_default()
+        };
+        match node.kind() {
+            "scoped_use_list" => {
+                let path = join(&path(node));
+                if let Some(list) = node.child_by_field_name("list") {
+                    self.lists.push(List {
+                        path: path.clone(),
+                        node: list,
+                        statement: self.statement,
+                    });
+                    self.flatten(list, &path);
+                }
+            }
+            "use_list" => {
+                for item in named_children(node) {
+                    self.flatten(item, prefix);
+                }
+            }
+            "use_as_clause" => {
+                let alias = node.child_by_field_name("alias").map(|a| text(self.doc, a));
+                self.push(node, join(&path(node)), alias);
+            }
+            "self" => self.push(node, prefix.to_owned(), None),
+            "line_comment" | "block_comment" => {}
+            _ => self.push(node, join(&text(self.doc, node)), None),
+        }
+    }
+
+    fn push(&mut self, node: Node<'t>, path: String, alias: Option<String>) {
+        let (module, item) = path.rsplit_once("::").unwrap_or(("", &path));
+        self.imports.push(Import {
+            spec: Spec {
+                module: module.to_owned(),
+                item: item.to_owned(),
+                name: alias.clone().unwrap_or_else(|| item.to_owned()),
+                alias: alias.is_some(),
+            },
+            node,
+            statement: self.statement,
+            public: self.public,
+        });
+    }
+}
+
+/// Check if `name` is used outside of the imports of `doc`.
+fn is_used(doc: &Document, name: &str) -> bool {
+    let statements = statements(doc);
+    let in_import = |node: Node| {
+        statements
+            .iter()
+            .any(|s| s.start_byte() <= node.start_byte() && node.end_byte() <= s.end_byte())
+    };
+    let mut cursor = doc.tree().walk();
+    let mut stack = vec![doc.tree().root_node()];
+    while let Some(node) = stack.pop() {
+        if matches!(node.kind(), "identifier" | "type_identifier")
+            && doc.content()[node.byte_range()] == *name
+            && !in_import(node)
+        {
+            return true;
+        }
+        stack.extend(node.named_children(&mut cursor));
+    }
+    false
+}
+
+/// Rust traits are used by calling their methods without naming them. An
+/// import which could be a trait is only known to be unused if the edits of
+/// the script removed the last use of its name, `use a::Trait as _` is kept.
+fn maybe_trait(doc: &Document, spec: &Spec) -> bool {
+    spec.name == "_" || spec.name.starts_with(char::is_uppercase) && !is_used(doc, &spec.name)
+}
+
+/// An existing import to merge new items from `module` into: a list of items
+/// from the module, a single item from it or the module itself.
+fn merge_target<'t>(
+    doc: &'t Document,
+    imports: &[Import<'t>],
+    lists: &[List<'t>],
+    module: &str,
+    plan: &Plan,
+    edited: &dyn Fn(Node) -> bool,
+) -> Option<(Node<'t>, Merge)> {
+    let free =
+        |node: Node, statement: Node| !plan.add.contains_key(&node.id()) && !edited(statement);
+    if doc.language() != Language::Rust {
+        return statements(doc)
+            .into_iter()
+            .find(|s| {
+                let wildcard = named_children(*s).any(|c| c.kind() == "wildcard_import");
+                s.child_by_field_name("module_name")
+                    .is_some_and(|m| text(doc, m) == module)
+                    && !wildcard
+                    && free(*s, *s)
+            })
+            .map(|s| (s, Merge::List));
+    }
+
+    let private =
+        |statement: Node| !named_children(statement).any(|c| c.kind() == "visibility_modifier");
+    let list = lists
+        .iter()
+        .find(|l| l.path == module && private(l.statement) && free(l.node, l.statement));
+    if let Some(list) = list {
+        return Some((list.node, Merge::List));
+    }
+    let leaf = |i: &&Import| {
+        !i.public
+            && matches!(
+                i.node.kind(),
+                "identifier" | "scoped_identifier" | "use_as_clause"
+            )
+            && free(i.node, i.statement)
+    };
+    let item = imports
+        .iter()
+        .filter(leaf)
+        .find(|i| i.spec.module == module);
+    if let Some(item) = item {
+        return Some((item.node, Merge::Item));
+    }
+    imports
+        .iter()
+        .filter(leaf)
+        .find(|i| !i.spec.alias && format!("{}::{}", i.spec.module, i.spec.item) == module)
+        .map(|i| (i.node, Merge::Module))
+}
+
+/// Render the Rust use tree `node` with the changes of `plan`.
+fn render(doc: &Document, node: Node, plan: &Plan) -> Render {
+    let removed = plan.remove.contains(&node.id());
+    match node.kind() {
+        "scoped_use_list" => {
+            let path = node
+                .child_by_field_name("path")
+                .map_or(String::new(), |p| doc.content()[p.byte_range()].to_owned());
+            let Some(list) = node.child_by_field_name("list") else {
+                return Render::Unchanged;
+            };
+            match render(doc, list, plan) {
+                // `a::{self}` is `a`.
+                Render::Text(item) if item == "self" => Render::Text(path),
+                Render::Text(items) => Render::Text(format!("{path}::{items}")),
+                r => r,
+            }
+        }
+        "use_list" => {
+            let items = named_children(node)
+                .map(|c| match c.kind() {
+                    "line_comment" | "block_comment" => Entry::Comment(c),
+                    _ => Entry::Item(c, render(doc, c, plan)),
+                })
+                .collect();
+            render_list(doc, node, items, plan.add.get(&node.id()), true)
+        }
+        _ => match plan.add.get(&node.id()) {
+            Some((merge, items)) => merge_into(doc, node, *merge, removed, items),
+            None if removed => Render::Removed,
+            None => Render::Unchanged,
+        },
+    }
+}
+
+/// Render the single Rust import `node` with the new `items` next to it.
+fn merge_into(doc: &Document, node: Node, merge: Merge, removed: bool, items: &[String]) -> Render {
+    let source = doc.content()[node.byte_range()].to_owned();
+    // Split `a::B as C` into the path `a` and the item `B as C`.
+    let (base, own) = match merge {
+        Merge::Module => (source, "self".to_owned()),
+        _ => {
+            let path = match node.kind() {
+                "use_as_clause" => node.child_by_field_name("path").unwrap_or(node),
+                _ => node,
+            };
+            let path_text = &doc.content()[path.byte_range()];
+            let rest = &doc.content()[path.end_byte()..node.end_byte()];
+            match path_text.rsplit_once("::") {
+                Some((base, item)) => (base.to_owned(), format!("{item}{rest}")),
+                None => (String::new(), format!("{path_text}{rest}")),
+            }
+        }
+    };
+    let mut all = Vec::new();
+    if !removed {
+        all.push(own);
+    }
+    for item in items {
+        let k = all
+            .iter()
+            .position(|a| order(a) > order(item))
+            .unwrap_or(all.len());
+        all.insert(k, item.to_owned());
+    }
+    let items = match all.as_slice() {
+        [] => return Render::Removed,
+        [item] if item == "self" => return Render::Text(base),
+        [item] => item.to_owned(),
+        _ => format!("{{{}}}", all.join(", ")),
+    };
+    match base.as_str() {
+        "" => Render::Text(items),
+        base => Render::Text(format!("{base}::{items}")),
+    }
+}
+
+/// An item of a list with how it is rendered, or a comment between items.
+enum Entry<'t> {
+    Item(Node<'t>, Render),
+    Comment(Node<'t>),
+}
+
+/// Render the items of a Rust use list (with `braces`) or of a Python import
+/// statement with the `added` ones inserted in sorted order.
+///
+/// Lists spanning several lines keep one item per line with their trailing
+/// comments. A Rust list with a single item left is collapsed, `a::{B}` becomes
+/// `a::B`.
+fn render_list(
+    doc: &Document,
+    node: Node,
+    entries: Vec<Entry>,
+    added: Option<&(Merge, Vec<String>)>,
+    braces: bool,
+) -> Render {
+    let changed = entries
+        .iter()
+        .any(|e| matches!(e, Entry::Item(_, Render::Removed | Render::Text(_))));
+    if !changed && added.is_none() {
+        return Render::Unchanged;
+    }
+    let content = doc.content();
+    // Items with their trailing comments, and comments on lines of their own.
+    let mut lines: Vec<(Option<String>, Vec<&str>)> = Vec::new();
+    let mut previous: Option<Node> = None;
+    let mut removed = false;
+    for entry in entries.iter() {
+        match entry {
+            Entry::Item(item, render) => {
+                previous = Some(*item);
+                removed = matches!(render, Render::Removed);
+                let text = match render {
+                    Render::Removed => continue,
+                    Render::Unchanged => content[item.byte_range()].to_owned(),
+                    Render::Text(text) => text.to_owned(),
+                };
+                lines.push((Some(text), Vec::new()));
+            }
+            Entry::Comment(comment) => {
+                let comment_text = &content[comment.byte_range()];
+                let trailing =
+                    previous.is_some_and(|p| p.end_position().row == comment.start_position().row);
+                match lines.last_mut() {
+                    // A trailing comment goes with its item.
+                    Some((_, comments)) if trailing && !removed => comments.push(comment_text),
+                    _ if trailing => {}
+                    _ => lines.push((None, vec![comment_text])),
+                }
+            }
+        }
+    }
+    for item in added.map(|(_, items)| items.as_slice()).unwrap_or_default() {
+        let k = lines
+            .iter()
+            .position(|(i, _)| i.as_deref().is_some_and(|i| order(i) > order(item)))
+            .unwrap_or(lines.len());
+        lines.insert(k, (Some(item.to_owned()), Vec::new()));
+    }
+
+    let items = lines
+        .iter()
+        .filter_map(|(i, _)| i.as_deref())
+        .collect::<Vec<_>>();
+    let comments = lines.iter().any(|(_, c)| !c.is_empty());
+    match items.as_slice() {
+        [] => return Render::Removed,
+        [item] if braces && !comments => return Render::Text(item.to_string()),
+        _ => {}
+    }
+    let multiline = node.start_position().row != node.end_position().row;
+    let mut text = String::new();
+    if multiline {
+        let first = entries
+            .iter()
+            .map(|e| match e {
+                Entry::Item(n, _) | Entry::Comment(n) => *n,
+            })
+            .next();
+        let indent = first.map_or("    ", |n| indentation(content, n.start_byte()));
+        let last = lines.iter().rposition(|(i, _)| i.is_some()).unwrap_or(0);
+        for (k, (item, comments)) in lines.iter().enumerate() {
+            // Python statements keep the text after the last item.
+            if k > 0 || braces {
+                text.push('\n');
+                text.push_str(indent);
+            }
+            if let Some(item) = item {
+                text.push_str(item);
+                if braces || k < last {
+                    text.push(',');
+                }
+            }
+            for (n, comment) in comments.iter().enumerate() {
+                if item.is_some() || n > 0 {
+                    text.push(' ');
+                }
+                text.push_str(comment);
+            }
+        }
+        if braces {
+            text.push('\n');
+            text.push_str(indentation(content, node.end_byte()));
+        }
+    } else {
+        let mut pending = Vec::new();
+        let mut parts = Vec::new();
+        for (item, comments) in lines.iter() {
+            match item {
+                Some(item) => {
+                    pending.push(item.as_str());
+                    pending.extend(comments.iter().copied());
+                    parts.push(pending.join(" "));
+                    pending.clear();
+                }
+                None => pending.extend(comments.iter().copied()),
+            }
+        }
+        text = parts.join(", ");
+    }
+    match braces {
+        true => Render::Text(format!("{{{text}}}")),
+        false => Render::Text(text),
+    }
+}
+
+/// Render the names of the Python import `statement` with the changes of
+/// `plan`, with the bytes to replace.
+fn render_names(
+    doc: &Document,
+    statement: Node,
+    plan: &Plan,
+) -> Option<(std::ops::Range<usize>, Render)> {
+    let names = names(statement).collect::<Vec<_>>();
+    let range = names.first()?.start_byte()..names.last()?.end_byte();
+    let entries = named_children(statement)
+        .filter(|c| range.start <= c.start_byte() && c.end_byte() <= range.end)
+        .filter_map(|c| match c.kind() {
+            "comment" => Some(Entry::Comment(c)),
+            _ if names.contains(&c) => Some(match plan.remove.contains(&c.id()) {
+                true => Entry::Item(c, Render::Removed),
+                false => Entry::Item(c, Render::Unchanged),
+            }),
+            _ => None,
+        })
+        .collect();
+    let added = plan.add.get(&statement.id());
+    Some((range, render_list(doc, statement, entries, added, false)))
+}
+
+/// Sort key of an item in a list, `self` comes first.
+fn order(item: &str) -> (bool, &str) {
+    (item != "self", item)
+}
+
+/// Whitespace at the start of the line of `byte`.
+fn indentation(content: &str, byte: usize) -> &str {
+    let start = content[..byte].rfind('\n').map_or(0, |i| i + 1);
+    let line = &content[start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Where to insert new import `lines`: after the last import, or else before
+/// the first statement after inner attributes, doc comments and docstrings.
+fn insertion(doc: &Document, lines: Vec<String>) -> (usize, String) {
+    let content = doc.content();
+    let root = doc.tree().root_node();
+    let mut text = lines.join("\n") + "\n";
+    let last = named_children(root)
+        .filter(|n| {
+            matches!(
+                n.kind(),
+                "use_declaration"
+                    | "import_statement"
+                    | "import_from_statement"
+                    | "future_import_statement"
+            )
+        })
+        .last();
+    if let Some(last) = last {
+        return match content[last.end_byte()..].find('\n') {
+            Some(i) => (last.end_byte() + i + 1, text),
+            None => (content.len(), format!("\n{text}")),
+        };
+    }
+    let mut docstring = true;
+    let first = named_children(root).find(|n| match n.kind() {
+        "inner_attribute_item" | "shebang" | "comment" => false,
+        "line_comment" | "block_comment" => n.child_by_field_name("inner").is_none(),
+        // Only the first statement of a module is its docstring.
+        "expression_statement" if std::mem::take(&mut docstring) => {
+            n.named_child(0).is_none_or(|s| s.kind() != "string")
+        }
+        _ => true,
+    });
+    match first {
+        Some(node) => {
+            let start = content[..node.start_byte()]
+                .rfind('\n')
+                .map_or(0, |i| i + 1);
+            text.push('\n');
+            (start, text)
+        }
+        None if content.is_empty() || content.ends_with('\n') => (content.len(), text),
+        None => (content.len(), format!("\n{text}")),
+    }
+}
+
+/// The imported names of a Python import statement.
+fn names<'t>(node: Node<'t>) -> impl Iterator<Item = Node<'t>> {
+    let module = node.child_by_field_name("module_name");
+    named_children(node)
+        .filter(move |c| Some(*c) != module && matches!(c.kind(), "dotted_name" | "aliased_import"))
+}
+
+/// Bytes of the lines of `node`, including the line break.
+fn line_range(doc: &Document, node: Node) -> std::ops::Range<usize> {
+    let content = doc.content();
+    let mut start = node.start_byte();
+    let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+    if content[line_start..start].trim().is_empty() {
+        start = line_start;
+    }
+    let mut end = node.end_byte();
+    let rest = &content[end..];
+    let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+    if rest[..line_end].trim().is_empty() {
+        end += line_end;
+    }
+    start..end
+}
+
+/// Extend the removed lines of `range` by a blank line which would otherwise
+/// be left over next to another blank line or at the start or end of the file.
+fn without_blank_line(content: &str, range: std::ops::Range<usize>) -> std::ops::Range<usize> {
+    let before = &content[..range.start];
+    let after = &content[range.end..];
+    let blank_before = before.is_empty() || before.ends_with("\n\n") || before.ends_with("\n\r\n");
+    let blank_after = ["\n", "\r\n"].into_iter().find(|b| after.starts_with(b));
+    match blank_after {
+        Some(blank) if blank_before => range.start..range.end + blank.len(),
+        None if after.is_empty() && !before.is_empty() => {
+            let trimmed = before.trim_end_matches(['\r', '\n']);
+            let line_end = before[trimmed.len()..]
+                .find('\n')
+                .map_or(before.len(), |i| trimmed.len() + i + 1);
+            line_end..range.end
+        }
+        _ => range,
+    }
+}
+
+fn overlaps(range: &tree_sitter::Range, node: Node) -> bool {
+    range.start_byte < node.end_byte() && node.start_byte() < range.end_byte
+}
+
+fn named_children(node: Node) -> impl Iterator<Item = Node> {
+    (0..node.named_child_count()).filter_map(move |i| node.named_child(i))
+}
+
+/// Text of `node` without whitespace, e.g. a path split over several lines.
+fn text(doc: &Document, node: Node) -> String {
+    doc.content()[node.byte_range()]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply the import `requests` to `content`.
+    fn apply(lang: Language, content: &str, requests: &[Request]) -> String {
+        rewrite(lang, content, None, requests)
+    }
+
+    /// Apply the import `requests` to `content` after the script replaced the
+    /// last occurrence of the first text of `edit` with the second one.
+    fn rewrite(
+        lang: Language,
+        content: &str,
+        edit: Option<(&str, &str)>,
+        requests: &[Request],
+    ) -> String {
+        let doc = crate::testing::doc(lang, content);
+        let mut edits = Vec::new();
+        if let Some((old, new)) = edit {
+            let start = content.rfind(old).unwrap();
+            edits.push(Change::new(
+                doc.range(start..start + old.len()),
+                new.to_owned(),
+            ));
+        }
+        let imports = changes(&doc, requests, &edits).unwrap();
+        edits.extend(imports);
+        doc.apply_edits(edits).unwrap().content().to_owned()
+    }
+
+    fn ensure(import: &str) -> Request {
+        Request::Ensure(import.to_owned())
+    }
+
+    fn remove(import: &str) -> Request {
+        Request::RemoveUnused(import.to_owned())
+    }
+
+    #[test]
+    fn merge_rust() {
+        let rust = |content, requests: &[Request]| apply(Language::Rust, content, requests);
+        assert_eq!(
+            rust(
+                "use std::collections::{BTreeMap, HashSet};\n",
+                &[ensure("std::collections::HashMap")]
+            ),
+            "use std::collections::{BTreeMap, HashMap, HashSet};\n"
+        );
+        assert_eq!(
+            rust(
+                "use std::collections::HashSet;\n",
+                &[ensure("std::collections::HashMap")]
+            ),
+            "use std::collections::{HashMap, HashSet};\n"
+        );
+        assert_eq!(
+            rust(
+                "use std::fmt;\n\nimpl fmt::Debug for A {}\n",
+                &[ensure("std::fmt::Display")]
+            ),
+            "use std::fmt::{self, Display};\n\nimpl fmt::Debug for A {}\n"
+        );
+        assert_eq!(
+            rust("use a::{\n    B, // b\n    D,\n};\n", &[ensure("a::C")]),
+            "use a::{\n    B, // b\n    C,\n    D,\n};\n"
+        );
+        assert_eq!(
+            rust("use a::B;\n", &[ensure("c::D")]),
+            "use a::B;\nuse c::D;\n"
+        );
+    }
+
+    #[test]
+    fn remove_and_ensure_in_one_statement() {
+        let requests = [
+            remove("std::collections::HashSet"),
+            ensure("std::collections::HashMap"),
+        ];
+        let rust = |content: &str| {
+            let content = format!("{content}\nfn f(_: HashSet<u8>) {{}}\n");
+            let edit = Some(("HashSet", "HashMap"));
+            rewrite(Language::Rust, &content, edit, &requests)
+        };
+        assert_eq!(
+            rust("use std::collections::{BTreeSet, HashSet};\n"),
+            "use std::collections::{BTreeSet, HashMap};\n\nfn f(_: HashMap<u8>) {}\n"
+        );
+        assert_eq!(
+            rust("use std::collections::{HashMap, HashSet};\n"),
+            "use std::collections::HashMap;\n\nfn f(_: HashMap<u8>) {}\n"
+        );
+        assert_eq!(
+            rust("use std::collections::HashSet;\n"),
+            "use std::collections::HashMap;\n\nfn f(_: HashMap<u8>) {}\n"
+        );
+
+        let requests = [remove("from m import b"), ensure("from m import c")];
+        assert_eq!(
+            apply(Language::Python, "from m import a, b\n", &requests),
+            "from m import a, c\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Python,
+                "from m import (\n    a,\n    b,\n)\n",
+                &requests
+            ),
+            "from m import (\n    a,\n    c,\n)\n"
+        );
+    }
+
+    #[test]
+    fn prune_to_one_item() {
+        assert_eq!(
+            apply(
+                Language::Rust,
+                "use a::{b, c};\n\nfn f() { b(); }\n",
+                &[remove("a::c")]
+            ),
+            "use a::b;\n\nfn f() { b(); }\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Rust,
+                "use a::{self, c};\n\nfn f(_: a::B) {}\n",
+                &[remove("a::c")]
+            ),
+            "use a;\n\nfn f(_: a::B) {}\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Python,
+                "from m import a, b\n\na()\n",
+                &[remove("from m import b")]
+            ),
+            "from m import a\n\na()\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Rust,
+                "use a::{b::{c, d}, E};\n\nfn f(_: E) {}\n",
+                &[remove("a::b::c"), remove("a::b::d")]
+            ),
+            "use a::E;\n\nfn f(_: E) {}\n"
+        );
+    }
+
+    #[test]
+    fn remove_last_item() {
+        let content = "use a::{b, c};\nuse d::E;\n\nfn f(_: E) {}\n";
+        let requests = [remove("a::b"), remove("a::c"), remove("d::E")];
+        assert_eq!(
+            apply(Language::Rust, content, &requests),
+            "use d::E;\n\nfn f(_: E) {}\n"
+        );
+        // No blank line is left where the imports were.
+        let requests = [remove("a::b")];
+        assert_eq!(
+            apply(Language::Rust, "use a::b;\n\nfn f() {}\n", &requests),
+            "fn f() {}\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Rust,
+                "//! A.\n\nuse a::b;\n\nfn f() {}\n",
+                &requests
+            ),
+            "//! A.\n\nfn f() {}\n"
+        );
+        assert_eq!(
+            apply(Language::Rust, "fn f() {}\n\nuse a::b;\n", &requests),
+            "fn f() {}\n"
+        );
+        let content = "import os\nfrom m import a, b\n\nos.exit()\n";
+        let requests = [
+            remove("from m import a"),
+            remove("from m import b"),
+            remove("import os"),
+        ];
+        assert_eq!(
+            apply(Language::Python, content, &requests),
+            "import os\n\nos.exit()\n"
+        );
+    }
+
+    #[test]
+    fn keep_traits() {
+        // Traits are used through their methods without naming them.
+        let content = "use std::io::Write;\n\nfn f(mut w: File) {\n    w.write_all(b\"\");\n}\n";
+        let requests = [remove("std::io::Write")];
+        assert_eq!(apply(Language::Rust, content, &requests), content);
+        let content = content.replace("Write;", "Write as _;");
+        assert_eq!(apply(Language::Rust, &content, &requests), content);
+
+        // A type is removed once the script removed its last use.
+        let content = "use std::collections::HashSet;\n\nfn f(_: HashSet<u8>) {}\n";
+        let requests = [remove("std::collections::HashSet")];
+        assert_eq!(
+            rewrite(Language::Rust, content, Some(("HashSet", "Vec")), &requests),
+            "fn f(_: Vec<u8>) {}\n"
+        );
+    }
+
+    #[test]
+    fn insert_after_header() {
+        assert_eq!(
+            apply(
+                Language::Rust,
+                "//! Docs.\n#![allow(dead_code)]\n\nfn f() {}\n",
+                &[ensure("a::B")]
+            ),
+            "//! Docs.\n#![allow(dead_code)]\n\nuse a::B;\n\nfn f() {}\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Python,
+                "#!/usr/bin/env python\n\"\"\"Docs.\"\"\"\n\nf()\n",
+                &[ensure("import os")]
+            ),
+            "#!/usr/bin/env python\n\"\"\"Docs.\"\"\"\n\nimport os\n\nf()\n"
+        );
+        assert_eq!(
+            apply(
+                Language::Python,
+                "\"\"\"Docs.\"\"\"\nfrom __future__ import annotations\n",
+                &[ensure("import os")]
+            ),
+            "\"\"\"Docs.\"\"\"\nfrom __future__ import annotations\nimport os\n"
+        );
+    }
+}
@@ -11,6 +11,21 @@ pub enum Language {
 pub struct Error;
 
 impl Language {
+    pub const ALL: [Self; 3] = [Self::Bazel, Self::Python, Self::Rust];
+
+    /// Detect the language of a file by its name, using the same file types as
+    /// the file walker.
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        Self::ALL.into_iter().find(|lang| {
+            let mut types = ignore::types::TypesBuilder::new();
+            types.add_defaults();
+            types.select(lang.as_str());
+            types
+                .build()
+                .is_ok_and(|t| t.matched(path, false).is_whitelist())
+        })
+    }
+
     pub(crate) fn language(&self) -> tree_sitter::Language {
         match self {
             Self::Bazel | Self::Python => tree_sitter_python::language(),
@@ -18,6 +33,14 @@ impl Language {
         }
     }
 
+    /// Kinds of the CST nodes which represent comments.
+    pub(crate) fn comment_kinds(&self) -> &'static [&'static str] {
+        match self {
+            Self::Bazel | Self::Python => &["comment"],
+            Self::Rust => &["line_comment", "block_comment"],
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         // https://github.com/BurntSushi/ripgrep/blob/master/crates/ignore/src/default_types.rs#L12
         match self {
@@ -1,10 +1,57 @@
+mod baseline;
 mod doc;
+mod formatter;
+mod git;
+mod group;
+mod import;
 mod lang;
+pub mod lsp;
 mod query;
+pub mod repl;
+mod report;
+mod rule;
+mod script;
+mod suppress;
+#[cfg(test)]
+mod testing;
+mod watch;
+mod workspace;
 
+pub use baseline::Baseline;
+pub use baseline::Error as BaselineError;
 pub use doc::Capture;
+pub use doc::Change;
 pub use doc::Document;
+pub use doc::Error as DocumentError;
+pub use doc::Indent;
+pub use doc::LineEnding;
 pub use doc::Match;
+pub use doc::OpenOptions;
+pub use doc::Skip;
+pub use doc::TreeFormat;
+pub use doc::TreeOptions;
+pub use formatter::Error as FormatterError;
+pub use formatter::Formatter;
+pub use git::Changes;
+pub use git::Error as GitError;
+pub use group::Groups;
 pub use lang::Language;
 pub use query::Error as QueryError;
 pub use query::Query;
+pub use report::write_reports;
+pub use report::Format;
+pub use report::Report;
+pub use report::Severity;
+pub use rule::Error as RuleError;
+pub use rule::Rule;
+pub use rule::Rules;
+pub use script::Facts;
+pub use script::Filter;
+pub use script::Script;
+pub use script::ScriptOutput;
+pub use suppress::Suppressions;
+pub use suppress::UNUSED_SUPPRESSION;
+pub use watch::Watcher;
+pub use workspace::Error as WorkspaceError;
+pub use workspace::FileChange;
+pub use workspace::Workspace;
@@ -0,0 +1,458 @@
+//
+// Language Server Protocol server which publishes findings of rules as
+// diagnostics and offers the changes of their scripts as code actions.
+//
+use crate::{Document, Language, Report, Rules, Severity, Suppressions};
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, LogMessage,
+        Notification as _, PublishDiagnostics,
+    },
+    request::{CodeActionRequest, Request as _},
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, LogMessageParams, MessageType,
+    NumberOrString, Position, PublishDiagnosticsParams, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+use std::{collections::HashMap, path::PathBuf};
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+type Result<T> = std::result::Result<T, Error>;
+
+/// Serve the given rules via LSP on stdin and stdout.
+pub fn serve(rules: Rules) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve_connection(&connection, rules)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Serve the given rules via LSP on `connection` until the client shuts down.
+pub fn serve_connection(connection: &Connection, rules: Rules) -> Result<()> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        connection,
+        rules,
+        documents: HashMap::new(),
+    };
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    break;
+                }
+                server.request(req)?;
+            }
+            Message::Notification(not) => server.notification(not)?,
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    rules: Rules,
+    documents: HashMap<Url, Document>,
+}
+
+impl Server<'_> {
+    fn request(&mut self, req: Request) -> Result<()> {
+        let resp = match req.method.as_str() {
+            CodeActionRequest::METHOD => {
+                match serde_json::from_value::<CodeActionParams>(req.params) {
+                    Ok(params) => Response::new_ok(req.id, self.code_actions(&params)?),
+                    Err(e) => Response::new_err(
+                        req.id,
+                        lsp_server::ErrorCode::InvalidParams as i32,
+                        e.to_string(),
+                    ),
+                }
+            }
+            _ => Response::new_err(
+                req.id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                format!("unsupported request: {}", req.method),
+            ),
+        };
+        self.connection.sender.send(Message::Response(resp))?;
+        Ok(())
+    }
+
+    fn notification(&mut self, not: Notification) -> Result<()> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Some(params) = self.params::<DidOpenTextDocumentParams>(not)? else {
+                    return Ok(());
+                };
+                let doc = params.text_document;
+                let language = doc.language_id.parse::<Language>().ok();
+                self.open(doc.uri, language, doc.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(params) = self.params::<DidChangeTextDocumentParams>(not)? else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                let language = self.documents.get(&uri).map(|d| d.language());
+                // Only full document sync is supported, the last change wins.
+                match params.content_changes.into_iter().last() {
+                    Some(change) => self.open(uri, language, change.text),
+                    None => Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let Some(params) = self.params::<DidCloseTextDocumentParams>(not)? else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.publish(uri, Vec::new())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parameters of a notification, invalid ones are logged and ignored so a
+    /// single bad message does not stop the server.
+    fn params<P: serde::de::DeserializeOwned>(&self, not: Notification) -> Result<Option<P>> {
+        match serde_json::from_value(not.params) {
+            Ok(params) => Ok(Some(params)),
+            Err(e) => {
+                let message = format!("invalid parameters of {}: {e}", not.method);
+                self.log(MessageType::ERROR, message)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn open(&mut self, uri: Url, language: Option<Language>, text: String) -> Result<()> {
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let Some(language) = language.or_else(|| Language::from_path(&path)) else {
+            return Ok(());
+        };
+        let doc = match Document::with_content(path, language, text) {
+            Ok(doc) => doc,
+            Err(e) => return self.log(MessageType::ERROR, e.to_string()),
+        };
+
+        let mut suppressions = Suppressions::new(&doc);
+        let mut diagnostics = Vec::new();
+        let mut errors = Vec::new();
+        for rule in self.rules.for_language(language) {
+            match rule.check(&doc) {
+                Ok(reports) => diagnostics.extend(
+                    reports
+                        .iter()
+                        .filter(|r| !suppressions.suppresses(r))
+                        .map(|r| diagnostic(&doc, r)),
+                ),
+                Err(e) => errors.push(format!("rule {}: {e}", rule.id())),
+            }
+        }
+        self.documents.insert(uri.to_owned(), doc);
+        for e in errors {
+            self.log(MessageType::ERROR, e)?;
+        }
+        self.publish(uri, diagnostics)
+    }
+
+    fn code_actions(&self, params: &CodeActionParams) -> Result<Vec<CodeActionOrCommand>> {
+        let uri = &params.text_document.uri;
+        let Some(doc) = self.documents.get(uri) else {
+            return Ok(Vec::new());
+        };
+        let range = tree_sitter::Range {
+            start_byte: offset(doc, params.range.start),
+            end_byte: offset(doc, params.range.end),
+            start_point: point(doc, params.range.start),
+            end_point: point(doc, params.range.end),
+        };
+
+        let mut actions = Vec::new();
+        for rule in self.rules.for_language(doc.language()) {
+            let changes = match rule.fix(doc, &range) {
+                Ok(changes) if !changes.is_empty() => changes,
+                Ok(_) => continue,
+                Err(e) => {
+                    self.log(MessageType::ERROR, format!("rule {}: {e}", rule.id()))?;
+                    continue;
+                }
+            };
+            let edits = changes
+                .iter()
+                .map(|c| TextEdit {
+                    range: lsp_range(doc, c.range()),
+                    new_text: if c.is_reindent() {
+                        doc.reindent(c.replacement(), c.range().start_point)
+                    } else {
+                        c.replacement().to_owned()
+                    },
+                })
+                .collect();
+            let diagnostics = params
+                .context
+                .diagnostics
+                .iter()
+                .filter(|d| d.code == Some(NumberOrString::String(rule.id().to_owned())))
+                .cloned()
+                .collect::<Vec<_>>();
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Apply ssr rule `{}`", rule.id()),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(diagnostics),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.to_owned(), edits)])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        }
+        Ok(actions)
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<Diagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let not = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+        self.connection.sender.send(Message::Notification(not))?;
+        Ok(())
+    }
+
+    fn log(&self, typ: MessageType, message: String) -> Result<()> {
+        let params = LogMessageParams { typ, message };
+        let not = Notification::new(LogMessage::METHOD.to_owned(), params);
+        self.connection.sender.send(Message::Notification(not))?;
+        Ok(())
+    }
+}
+
+fn diagnostic(doc: &Document, report: &Report) -> Diagnostic {
+    let severity = match report.severity() {
+        Severity::Hint => DiagnosticSeverity::HINT,
+        Severity::Info => DiagnosticSeverity::INFORMATION,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Error => DiagnosticSeverity::ERROR,
+    };
+    Diagnostic {
+        range: lsp_range(doc, report.range()),
+        severity: Some(severity),
+        code: report.rule().map(|r| NumberOrString::String(r.to_owned())),
+        source: Some(env!("CARGO_PKG_NAME").to_owned()),
+        message: report.message().to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Byte offset of the start of `row`.
+fn line_start(doc: &Document, row: usize) -> usize {
+    if row == 0 {
+        return 0;
+    }
+    doc.content()
+        .match_indices('\n')
+        .nth(row - 1)
+        .map(|(k, _)| k + 1)
+        .unwrap_or(doc.content().len())
+}
+
+fn line(doc: &Document, row: usize) -> &str {
+    let start = line_start(doc, row);
+    let line = &doc.content()[start..];
+    line.split('\n').next().unwrap_or_default()
+}
+
+fn lsp_range(doc: &Document, range: &tree_sitter::Range) -> lsp_types::Range {
+    lsp_types::Range {
+        start: position(doc, range.start_point),
+        end: position(doc, range.end_point),
+    }
+}
+
+/// Length of the byte order mark in UTF-16 code units at the start of `row`.
+///
+/// The document content has no byte order mark, while it is part of the text
+/// of the client and so of its first line.
+fn bom(doc: &Document, row: usize) -> usize {
+    if row == 0 && doc.has_bom() {
+        '\u{feff}'.len_utf16()
+    } else {
+        0
+    }
+}
+
+/// LSP positions count UTF-16 code units, tree-sitter columns are bytes.
+fn position(doc: &Document, point: tree_sitter::Point) -> Position {
+    let line = line(doc, point.row);
+    let prefix = line.get(..point.column).unwrap_or(line);
+    Position {
+        line: point.row as u32,
+        character: (bom(doc, point.row) + prefix.encode_utf16().count()) as u32,
+    }
+}
+
+fn point(doc: &Document, position: Position) -> tree_sitter::Point {
+    let row = position.line as usize;
+    let character = (position.character as usize).saturating_sub(bom(doc, row));
+    let mut units = 0;
+    let column = line(doc, row)
+        .char_indices()
+        .find(|(_, c)| {
+            let found = units >= character;
+            units += c.len_utf16();
+            found
+        })
+        .map(|(k, _)| k)
+        .unwrap_or_else(|| line(doc, row).len());
+    tree_sitter::Point { row, column }
+}
+
+fn offset(doc: &Document, position: Position) -> usize {
+    let point = point(doc, position);
+    (line_start(doc, point.row) + point.column).min(doc.content().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+        InitializeParams, InitializedParams, TextDocumentItem,
+    };
+
+    const RULES: &str = r#"
+[[rule]]
+id = "no-dbg"
+language = "rust"
+query = '((macro_invocation macro: (identifier) @m (#eq? @m "dbg")) @call)'
+message = "leftover dbg!"
+severity = "error"
+"#;
+
+    /// A scripted client talking to a server on another thread.
+    struct Client {
+        connection: Connection,
+        server: std::thread::JoinHandle<()>,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (connection, server) = Connection::memory();
+            let server = std::thread::spawn(move || {
+                let rules = Rules::parse("ssr.toml", RULES).unwrap();
+                serve_connection(&server, rules).unwrap()
+            });
+            let client = Self { connection, server };
+            client.request::<Initialize>(1, InitializeParams::default());
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: lsp_types::request::Request>(&self, id: i32, params: R::Params) {
+            let req = Request::new(id.into(), R::METHOD.to_owned(), params);
+            self.connection.sender.send(req.into()).unwrap();
+            match self.receive() {
+                Message::Response(resp) => assert!(resp.error.is_none(), "{resp:?}"),
+                msg => panic!("expected a response, got {msg:?}"),
+            }
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            let not = Notification::new(N::METHOD.to_owned(), params);
+            self.connection.sender.send(not.into()).unwrap();
+        }
+
+        fn receive(&self) -> Message {
+            let timeout = std::time::Duration::from_secs(10);
+            self.connection.receiver.recv_timeout(timeout).unwrap()
+        }
+
+        fn notification<N: lsp_types::notification::Notification>(&self) -> N::Params {
+            match self.receive() {
+                Message::Notification(not) if not.method == N::METHOD => {
+                    serde_json::from_value(not.params).unwrap()
+                }
+                msg => panic!("expected {}, got {msg:?}", N::METHOD),
+            }
+        }
+
+        fn open(&self, text: &str) {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: Url::parse("file:///tmp/main.rs").unwrap(),
+                    language_id: "rust".to_owned(),
+                    version: 1,
+                    text: text.to_owned(),
+                },
+            });
+        }
+
+        fn stop(self) {
+            self.request::<Shutdown>(2, ());
+            self.notify::<Exit>(());
+            self.server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn positions_include_byte_order_mark() {
+        let doc = |text| crate::testing::doc(Language::Rust, text);
+        let with_bom = doc("\u{feff}let é = 1;\nlet x = 2;\n");
+        let without = doc("let é = 1;\nlet x = 2;\n");
+        let p = |row, column| tree_sitter::Point { row, column };
+
+        assert_eq!(position(&without, p(0, 4)), Position::new(0, 4));
+        assert_eq!(position(&with_bom, p(0, 4)), Position::new(0, 5));
+        assert_eq!(position(&with_bom, p(0, 6)), Position::new(0, 6));
+        assert_eq!(position(&with_bom, p(1, 4)), Position::new(1, 4));
+        for point in [p(0, 0), p(0, 4), p(0, 6), p(1, 4)] {
+            assert_eq!(super::point(&with_bom, position(&with_bom, point)), point);
+        }
+        assert_eq!(offset(&with_bom, Position::new(0, 0)), 0);
+        assert_eq!(offset(&with_bom, Position::new(1, 0)), 12);
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let client = Client::start();
+        client.open("fn main() {\n    dbg!(1);\n}\n");
+        let params = client.notification::<PublishDiagnostics>();
+        assert_eq!(params.uri.path(), "/tmp/main.rs");
+        assert_eq!(params.diagnostics.len(), 1);
+        let d = &params.diagnostics[0];
+        assert_eq!(d.message, "leftover dbg!");
+        assert_eq!(d.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(d.range.start, Position::new(1, 4));
+        assert_eq!(d.range.end, Position::new(1, 11));
+        client.stop();
+    }
+
+    #[test]
+    fn invalid_notification_is_logged() {
+        let client = Client::start();
+        let not = Notification::new(DidOpenTextDocument::METHOD.to_owned(), 42);
+        client.connection.sender.send(not.into()).unwrap();
+        let log = client.notification::<LogMessage>();
+        assert_eq!(log.typ, MessageType::ERROR);
+
+        client.open("fn main() {}\n");
+        let params = client.notification::<PublishDiagnostics>();
+        assert!(params.diagnostics.is_empty());
+        client.stop();
+    }
+}
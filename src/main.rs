@@ -1,11 +1,20 @@
 //
 // Structured Search Replace (SSR)
 //
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    io::{IsTerminal, Write as _},
+    path::{Path, PathBuf},
+};
 
 use clap::{Args, Parser, Subcommand};
 
-use ssr::{Document, Language, Query};
+use ssr::{
+    Baseline, Change, Changes, Document, Facts, FileChange, Filter, Format, Formatter, Groups,
+    Language, OpenOptions, Query, Report, Rules, Script, Severity, Skip, Suppressions, TreeFormat,
+    TreeOptions, Workspace, WorkspaceError,
+};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 type Result<T> = std::result::Result<T, Error>;
@@ -28,6 +37,19 @@ enum SsrCommand {
 
     /// Use query to search in all files and use replace command to replace matches.
     Replace(Replace),
+
+    /// Run rules against all files and report findings.
+    ///
+    /// Exits with 0 if there are no findings, 1 if findings were reported and 2
+    /// on errors.
+    Check(Check),
+
+    /// Interactively develop queries and scripts against a file.
+    Repl(Repl),
+
+    /// Run a Language Server Protocol server on stdin and stdout which reports
+    /// findings of rules as diagnostics.
+    Lsp(Lsp),
 }
 
 #[derive(Debug, Args)]
@@ -35,15 +57,74 @@ struct Tree {
     /// Which language to use.
     #[arg(short, long)]
     language: Language,
+    /// Include anonymous nodes like punctuation and keywords.
+    #[arg(short, long)]
+    anonymous: bool,
+    /// Include the byte range of each node.
+    #[arg(long)]
+    bytes: bool,
+    /// Include the text of leaf nodes.
+    #[arg(long)]
+    text: bool,
+    /// Only show this many levels below the top node.
+    #[arg(long)]
+    depth: Option<usize>,
+    /// Only show the smallest subtree covering ROW:COL-ROW:COL (zero based).
+    #[arg(long, value_parser = parse_range)]
+    range: Option<(tree_sitter::Point, tree_sitter::Point)>,
+    /// Only show the subtrees of nodes in the field NAME, e.g. `body`.
+    #[arg(long, value_name = "NAME", conflicts_with = "suggest_query")]
+    field: Option<String>,
+    /// Print a query matching the smallest node covering `--range` instead of
+    /// the tree.
+    #[arg(long, requires = "range")]
+    suggest_query: bool,
+    /// Output format of the tree (sexp, json or dot).
+    #[arg(long, default_value_t = TreeFormat::Sexp)]
+    format: TreeFormat,
+    /// When to highlight `ERROR` and `MISSING` nodes (auto, always or never).
+    #[arg(long, value_name = "WHEN", default_value_t = clap::ColorChoice::Auto)]
+    color: clap::ColorChoice,
     /// Files to apply the query to
     file: PathBuf,
 }
 
+/// Parse `ROW:COL` or `ROW:COL-ROW:COL`.
+fn parse_range(s: &str) -> std::result::Result<(tree_sitter::Point, tree_sitter::Point), String> {
+    let point = |s: &str| {
+        let (row, column) = s.split_once(':')?;
+        Some(tree_sitter::Point {
+            row: row.trim().parse().ok()?,
+            column: column.trim().parse().ok()?,
+        })
+    };
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    point(start)
+        .zip(point(end))
+        .ok_or_else(|| format!("expected ROW:COL-ROW:COL, got `{s}`"))
+}
+
+/// Parse a size in bytes with an optional `K`, `M` or `G` suffix.
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let (number, unit) = match s.char_indices().last() {
+        Some((k, 'K' | 'k')) => (&s[..k], 1 << 10),
+        Some((k, 'M' | 'm')) => (&s[..k], 1 << 20),
+        Some((k, 'G' | 'g')) => (&s[..k], 1 << 30),
+        _ => (s, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("expected a size like 100K, got `{s}`"))
+}
+
 #[derive(Debug, Clone, Args)]
 struct QueryOptions {
     /// Which language to use.
-    #[arg(short, long)]
-    language: Language,
+    #[arg(short, long, required_unless_present = "stdin_filename")]
+    language: Option<Language>,
     /// Tree-Sitter query as s-expression:
     /// https://tree-sitter.github.io/tree-sitter/using-parsers#pattern-matching-with-queries
     #[arg(short = 'q', long = "query")]
@@ -51,8 +132,199 @@ struct QueryOptions {
 }
 
 impl QueryOptions {
-    fn query(&self) -> std::result::Result<Query, ssr::QueryError> {
-        Query::new(self.language, self.source.as_str())
+    /// The language given with `--language` or else the one of `path`.
+    fn language(&self, path: Option<&Path>) -> Result<Language> {
+        self.language
+            .or_else(|| path.and_then(Language::from_path))
+            .ok_or_else(|| "unknown language, use --language".into())
+    }
+
+    fn query(&self, language: Language) -> std::result::Result<Query, ssr::QueryError> {
+        Query::new(language, self.source.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+struct FilterOptions {
+    /// Rhai expression evaluated for every match, only matches for which it
+    /// returns `true` are kept.
+    #[arg(long, conflicts_with = "filter_file")]
+    filter: Option<String>,
+    /// Read the filter expression from a file.
+    #[arg(long)]
+    filter_file: Option<PathBuf>,
+}
+
+impl FilterOptions {
+    fn filter(&self) -> Result<Option<Filter>> {
+        let source = if let Some(source) = &self.filter {
+            source.to_owned()
+        } else if let Some(path) = &self.filter_file {
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Filter::new(&source)?))
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+struct RulesOptions {
+    /// Rules files with `[[rule]]` tables.
+    #[arg(short, long, default_value = "ssr.toml")]
+    rules: Vec<PathBuf>,
+}
+
+impl RulesOptions {
+    fn rules(&self) -> Result<Rules> {
+        let mut rules = Rules::default();
+        for p in self.rules.iter() {
+            rules.extend(Rules::open(p)?)?;
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+struct PrintOptions {
+    /// Show NUM lines after each capture.
+    #[arg(short = 'A', long, value_name = "NUM")]
+    after_context: Option<usize>,
+    /// Show NUM lines before each capture.
+    #[arg(short = 'B', long, value_name = "NUM")]
+    before_context: Option<usize>,
+    /// Show NUM lines before and after each capture.
+    #[arg(short = 'C', long, value_name = "NUM")]
+    context: Option<usize>,
+    /// When to highlight captures, line numbers and file names (auto, always
+    /// or never).
+    #[arg(long, value_name = "WHEN", default_value_t = clap::ColorChoice::Auto)]
+    color: clap::ColorChoice,
+    /// Only print the capture with this name for every match.
+    #[arg(long, value_name = "NAME")]
+    only_capture: Option<String>,
+}
+
+impl PrintOptions {
+    fn before(&self) -> usize {
+        self.before_context.or(self.context).unwrap_or(0)
+    }
+
+    fn after(&self) -> usize {
+        self.after_context.or(self.context).unwrap_or(0)
+    }
+
+    fn only_capture(&self) -> Option<&str> {
+        let name = self.only_capture.as_deref()?;
+        Some(name.strip_prefix('@').unwrap_or(name))
+    }
+
+    fn color(&self) -> bool {
+        use_color(self.color, &std::io::stdout())
+    }
+}
+
+/// Check if output to `out` should be colored when `choice` is selected.
+fn use_color(choice: clap::ColorChoice, out: &impl IsTerminal) -> bool {
+    match choice {
+        clap::ColorChoice::Always => true,
+        clap::ColorChoice::Never => false,
+        clap::ColorChoice::Auto => out.is_terminal(),
+    }
+}
+
+/// Number of files skipped by [`WalkOptions::open`] per reason.
+#[derive(Debug, Default)]
+struct Skipped(BTreeMap<Skip, usize>);
+
+impl Skipped {
+    /// Print the number of skipped files to stderr.
+    fn summary(&self) {
+        for (reason, n) in self.0.iter() {
+            eprintln!("skipped {n} {reason} file(s)");
+        }
+    }
+}
+
+/// A document to process, or `None` if it was skipped, and its path if known.
+type Input = (Option<PathBuf>, Result<Option<Document>>);
+
+/// Errors of single files collected with `--keep-going`, by kind.
+#[derive(Debug, Default)]
+struct Failures {
+    keep_going: bool,
+    errors: BTreeMap<&'static str, Vec<String>>,
+    /// Files which failed, they are not processed again in a later phase.
+    failed: BTreeSet<PathBuf>,
+}
+
+impl Failures {
+    fn new(keep_going: bool) -> Self {
+        Self {
+            keep_going,
+            ..Default::default()
+        }
+    }
+
+    /// Return the value of `result`, or with `--keep-going` record its error
+    /// and return `None`.
+    fn check<T>(&mut self, path: Option<&Path>, result: Result<T>) -> Result<Option<T>> {
+        let e = match result {
+            Ok(value) => return Ok(Some(value)),
+            Err(e) if !self.keep_going => return Err(e),
+            Err(e) => e,
+        };
+        let mut message = e.to_string();
+        if let Some(path) = path {
+            self.failed.insert(path.to_owned());
+        }
+        if let Some(path) = path.map(|p| p.display().to_string()) {
+            if !message.contains(&path) {
+                message = format!("{path}: {message}");
+            }
+        }
+        // Errors without a file, e.g. of the walker, can repeat in each phase.
+        let errors = self.errors.entry(error_kind(&e)).or_default();
+        if !errors.contains(&message) {
+            errors.push(message);
+        }
+        Ok(None)
+    }
+
+    fn has_failed(&self, path: &Path) -> bool {
+        self.failed.contains(path)
+    }
+
+    /// Print the errors to stderr and return the exit code for the run.
+    fn report(&self, code: std::process::ExitCode) -> std::process::ExitCode {
+        if self.errors.is_empty() {
+            return code;
+        }
+        for (kind, errors) in self.errors.iter() {
+            eprintln!("{kind} errors:");
+            for e in errors {
+                eprintln!("  {e}");
+            }
+        }
+        let n = self.errors.values().map(Vec::len).sum::<usize>();
+        eprintln!("error: {n} file(s) failed");
+        std::process::ExitCode::from(2)
+    }
+}
+
+fn error_kind(e: &Error) -> &'static str {
+    use ssr::DocumentError as E;
+    match e.downcast_ref::<E>() {
+        Some(E::Io(..)) => "I/O",
+        Some(E::Compile(_) | E::Script(..)) => "script",
+        Some(E::ParsingFailed | E::Language(_)) => "parse",
+        Some(E::Query(_)) => "query",
+        Some(E::Skipped(..)) => "skipped",
+        None if e.is::<ssr::FormatterError>() => "format",
+        None if e.is::<ignore::Error>() => "walk",
+        None if matches!(e.downcast_ref(), Some(WorkspaceError::Decoded(_))) => "encoding",
+        None => "other",
     }
 }
 
@@ -61,18 +333,177 @@ struct WalkOptions {
     /// Add a new file type.
     #[arg(long = "type-add")]
     type_defs: Vec<String>,
-    /// Paths to walk for files.
+    /// Only use files matching GLOB, a leading `!` excludes matching files
+    /// instead.
+    #[arg(short, long = "glob", value_name = "GLOB")]
+    globs: Vec<String>,
+    /// Also walk hidden files and directories.
+    #[arg(long)]
+    hidden: bool,
+    /// Do not respect `.gitignore`, `.ignore` and `.ssrignore` files.
+    #[arg(long)]
+    no_ignore: bool,
+    /// Descend at most NUM directories below the paths.
+    #[arg(long, value_name = "NUM")]
+    max_depth: Option<usize>,
+    /// Follow symbolic links.
+    #[arg(short = 'L', long)]
+    follow: bool,
+    /// Skip files larger than SIZE, with an optional K, M or G suffix.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_filesize: Option<u64>,
+    /// Also use files marked as generated with `@generated` or `DO NOT EDIT`.
+    #[arg(long)]
+    generated: bool,
+    /// Decode files with a UTF-16 byte order mark and files which are not
+    /// valid UTF-8 as Latin-1 instead of skipping them. Decoded files can not
+    /// be rewritten.
+    #[arg(long)]
+    decode: bool,
+    /// Continue with the next file when a file fails and report all errors at
+    /// the end.
+    #[arg(long)]
+    keep_going: bool,
+    /// Path of the document read from stdin, used to detect its language and
+    /// in the output.
+    #[arg(long, value_name = "PATH")]
+    stdin_filename: Option<PathBuf>,
+    /// Only use files changed in the git working tree since revision REV and
+    /// untracked files.
+    #[arg(long, value_name = "REV", group = "git")]
+    changed_since: Option<String>,
+    /// Only use files with changes staged in the git index.
+    #[arg(long, group = "git")]
+    staged: bool,
+    /// Only use the files listed in FILE, one per line (`-` for stdin).
+    #[arg(long, value_name = "FILE", conflicts_with = "git")]
+    files_from: Option<PathBuf>,
+    /// Only use matches which overlap lines changed according to git.
+    #[arg(long, requires = "git")]
+    changed_lines_only: bool,
+    /// Paths to walk for files, `-` reads a single document from stdin.
     paths: Vec<PathBuf>,
 }
 
 impl WalkOptions {
+    /// The path to show for the document on stdin if it is read instead of
+    /// walking the paths.
+    fn stdin(&self) -> Result<Option<PathBuf>> {
+        if !self.paths.iter().any(|p| p.as_os_str() == "-") {
+            return Ok(None);
+        }
+        if self.paths.len() > 1 {
+            return Err("`-` can not be combined with other paths".into());
+        }
+        Ok(Some(
+            self.stdin_filename
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("<stdin>")),
+        ))
+    }
+
+    /// Read the document on stdin, shown as `path`.
+    fn read_stdin(&self, path: PathBuf, language: Language) -> Result<Document> {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin().lock(), &mut content)
+            .map_err(|e| format!("failed to read stdin: {e}"))?;
+        Ok(Document::with_content(path, language, content)?)
+    }
+
+    fn open_options(&self) -> OpenOptions {
+        OpenOptions {
+            max_size: self.max_filesize,
+            generated: self.generated,
+            decode: self.decode,
+        }
+    }
+
+    /// Open the file at `path`, or count it in `skipped` if it is binary,
+    /// generated, too large or not UTF-8.
+    fn open(
+        &self,
+        path: &Path,
+        language: Language,
+        skipped: &mut Skipped,
+    ) -> Result<Option<Document>> {
+        match Document::open_with(path, language, &self.open_options()) {
+            Ok(doc) => Ok(Some(doc)),
+            Err(ssr::DocumentError::Skipped(_, reason)) => {
+                *skipped.0.entry(reason).or_default() += 1;
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Changes from git selected with `--changed-since` or `--staged`.
+    fn changes(&self) -> Result<Option<Changes>> {
+        Ok(match (&self.changed_since, self.staged) {
+            (Some(rev), _) => Some(Changes::since(rev)?),
+            (None, true) => Some(Changes::staged()?),
+            (None, false) => None,
+        })
+    }
+
+    /// Changes to restrict matches to with `--changed-lines-only`.
+    fn changed_lines(&self) -> Result<Option<Changes>> {
+        if self.changed_lines_only {
+            self.changes()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Files selected with `--files-from` or from git, below the given paths.
+    ///
+    /// This reads stdin or runs git, so it is called once per run and the
+    /// result is passed to [`Self::walker`].
+    fn selected(&self) -> Result<Option<Vec<PathBuf>>> {
+        let files = if let Some(list) = &self.files_from {
+            let list = if list.as_os_str() == "-" {
+                std::io::read_to_string(std::io::stdin().lock())
+                    .map_err(|e| format!("failed to read stdin: {e}"))?
+            } else {
+                std::fs::read_to_string(list)
+                    .map_err(|e| format!("failed to read {}: {e}", list.display()))?
+            };
+            list.lines()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        } else if let Some(changes) = self.changes()? {
+            let cwd = std::env::current_dir()?.canonicalize()?;
+            changes
+                .files()
+                .map(|p| p.strip_prefix(&cwd).unwrap_or(p).to_owned())
+                .collect()
+        } else {
+            return Ok(None);
+        };
+        let roots = self
+            .paths
+            .iter()
+            .map(|p| p.canonicalize())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let files = files
+            .into_iter()
+            .filter(|p| {
+                roots.is_empty()
+                    || p.canonicalize()
+                        .is_ok_and(|p| roots.iter().any(|r| p.starts_with(r)))
+            })
+            .collect();
+        Ok(Some(files))
+    }
+
+    /// Walk the files of `language`, only the `selected` ones if any.
     fn walker(
         &self,
         language: &str,
-    ) -> std::result::Result<
-        impl Iterator<Item = std::result::Result<ignore::DirEntry, ignore::Error>>,
-        ignore::Error,
-    > {
+        selected: Option<&[PathBuf]>,
+    ) -> Result<Box<dyn Iterator<Item = std::result::Result<ignore::DirEntry, ignore::Error>>>>
+    {
         let types = {
             let mut types = ignore::types::TypesBuilder::new();
             types.add_defaults();
@@ -83,33 +514,185 @@ impl WalkOptions {
             types.build()?
         };
 
-        let cwd = PathBuf::from(".");
-        let mut paths = self.paths.iter().fuse();
-        let mut w = ignore::WalkBuilder::new(paths.next().unwrap_or(&cwd));
-        for p in paths {
-            w.add(p);
-        }
-        w.types(types);
-        let iter = w.build().filter(|p| {
-            p.as_ref()
-                .map(|p| {
-                    if let Ok(m) = p.metadata() {
-                        m.is_file()
-                    } else {
-                        false
-                    }
-                })
-                .unwrap_or(false)
+        let overrides = {
+            let mut overrides = ignore::overrides::OverrideBuilder::new(".");
+            for glob in self.globs.iter() {
+                overrides.add(glob)?;
+            }
+            overrides.build()?
+        };
+
+        let mut w = if let Some(files) = selected {
+            // Files given explicitly are not filtered by the walker.
+            let mut files = files.iter().filter(|p| {
+                types.matched(p, false).is_whitelist() && !overrides.matched(p, false).is_ignore()
+            });
+            let Some(first) = files.next() else {
+                return Ok(Box::new(std::iter::empty()));
+            };
+            let mut w = ignore::WalkBuilder::new(first);
+            for p in files {
+                w.add(p);
+            }
+            w
+        } else {
+            let cwd = PathBuf::from(".");
+            let mut paths = self.paths.iter().fuse();
+            let mut w = ignore::WalkBuilder::new(paths.next().unwrap_or(&cwd));
+            for p in paths {
+                w.add(p);
+            }
+            w.types(types).overrides(overrides);
+            w
+        };
+        w.hidden(!self.hidden)
+            .ignore(!self.no_ignore)
+            .git_ignore(!self.no_ignore)
+            .git_global(!self.no_ignore)
+            .git_exclude(!self.no_ignore)
+            .parents(!self.no_ignore)
+            .max_depth(self.max_depth)
+            .follow_links(self.follow);
+        if !self.no_ignore {
+            w.add_custom_ignore_filename(".ssrignore");
+        }
+        // Errors are kept, they are handled like the errors of a file.
+        let iter = w.build().filter(|p| match p {
+            Ok(p) => p.metadata().is_ok_and(|m| m.is_file()),
+            Err(_) => true,
         });
-        Ok(iter)
+        Ok(Box::new(iter))
+    }
+
+    /// All files for the given languages, keyed by their canonical path.
+    fn files(
+        &self,
+        languages: &[Language],
+        selected: Option<&[PathBuf]>,
+    ) -> Result<BTreeMap<PathBuf, (PathBuf, Language)>> {
+        let mut files = BTreeMap::new();
+        for &language in languages {
+            for p in self.walker(language.as_str(), selected)? {
+                let p = p?;
+                let key = p.path().canonicalize().map_err(|e| {
+                    let p = p.path().display();
+                    format!("failed to resolve {p}: {e}")
+                })?;
+                files.entry(key).or_insert((p.path().to_owned(), language));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Evaluate all files and show the results, then re-evaluate files when
+    /// they change, until interrupted.
+    ///
+    /// Only files selected by the walker are evaluated. The `show` callback
+    /// receives the results of all files and the keys of the files which were
+    /// updated.
+    fn watch<T>(
+        &self,
+        languages: &[Language],
+        mut eval: impl FnMut(&Path, Language) -> Result<T>,
+        mut show: impl FnMut(&BTreeMap<PathBuf, T>, &BTreeSet<PathBuf>) -> Result<()>,
+    ) -> Result<std::process::ExitCode> {
+        if self.stdin()?.is_some() {
+            return Err("can not watch stdin".into());
+        }
+        let roots = if self.paths.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            self.paths.clone()
+        };
+        let roots = roots
+            .iter()
+            .map(|p| p.canonicalize())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let watcher = ssr::Watcher::new(&roots)?;
+        let selected = self.selected()?;
+
+        let mut results = BTreeMap::new();
+        let mut changed: Option<BTreeSet<PathBuf>> = None;
+        loop {
+            let files = self.files(languages, selected.as_deref())?;
+            let before = results.len();
+            results.retain(|k, _| files.contains_key(k));
+            let removed = before != results.len();
+
+            let mut updated = BTreeSet::new();
+            for (key, (path, language)) in files.iter() {
+                let is_changed = changed.as_ref().is_none_or(|c| c.contains(key));
+                if !is_changed && results.contains_key(key) {
+                    continue;
+                }
+                match eval(path, *language) {
+                    Ok(r) => {
+                        results.insert(key.to_owned(), r);
+                    }
+                    Err(e) => {
+                        eprintln!("error: {e}");
+                        results.remove(key);
+                    }
+                }
+                updated.insert(key.to_owned());
+            }
+            if changed.is_none() || removed || !updated.is_empty() {
+                show(&results, &updated)?;
+                eprintln!("watching {} file(s) for changes...", files.len());
+            }
+
+            let c = watcher.wait()?;
+            changed = Some(
+                c.into_iter()
+                    .map(|p| p.canonicalize().unwrap_or(p))
+                    .collect(),
+            );
+        }
     }
 }
 
 #[derive(Debug, Args)]
+#[command(group = clap::ArgGroup::new("mode").conflicts_with("findings"))]
 struct Search {
     #[command(flatten)]
     query: QueryOptions,
     #[command(flatten)]
+    filter: FilterOptions,
+    /// Script evaluated for every match, findings reported via
+    /// `document.report(range, message, severity)` are printed instead of the
+    /// matches.
+    #[arg(short, long, group = "findings")]
+    script: Option<String>,
+    /// Print a table of the distinct texts of a capture with the number of
+    /// matches and files and example locations instead of the matches.
+    #[arg(long, value_name = "@NAME", group = "findings")]
+    group_by: Option<String>,
+    /// Output format of the reported findings or the table of `--group-by`
+    /// (text, json, sarif or csv).
+    #[arg(long, requires = "findings")]
+    format: Option<Format>,
+    /// Keep running and search files again when they change.
+    #[arg(short, long, conflicts_with_all = ["quiet", "group_by"])]
+    watch: bool,
+    /// Stop searching a file after NUM matches.
+    #[arg(long, value_name = "NUM")]
+    max_count: Option<usize>,
+    /// Only print the number of matches of every file.
+    #[arg(long, group = "mode")]
+    count: bool,
+    /// Only print the paths of files with matches.
+    #[arg(long, group = "mode")]
+    files_with_matches: bool,
+    /// Only print the paths of files without matches.
+    #[arg(long, group = "mode")]
+    files_without_match: bool,
+    /// Print nothing and exit with 0 as soon as a match is found, like
+    /// `grep -q`. There is no short flag since `-q` is `--query`.
+    #[arg(long, visible_alias = "silent", group = "mode")]
+    quiet: bool,
+    #[command(flatten)]
+    print: PrintOptions,
+    #[command(flatten)]
     walk: WalkOptions,
 }
 
@@ -117,19 +700,82 @@ struct Search {
 struct Replace {
     #[command(flatten)]
     query: QueryOptions,
-    /// Replacement script.
+    /// Replacement script. A script with `fn collect(document, found)` and
+    /// `fn rewrite(document, found, workspace)` runs `collect` for every match
+    /// in all files first, recording facts in the map `this`, and then
+    /// `rewrite` for every match with the facts as `this`.
     #[arg(short, long)]
     replacement: String,
+    /// Indent the second and following lines of multi-line replacements like
+    /// the line they are inserted at, with the indentation of the file.
+    #[arg(long)]
+    reindent: bool,
+    /// Print the rewritten document read from stdin instead of a patch, e.g.
+    /// to use ssr as an editor filter.
+    #[arg(long)]
+    content: bool,
+    /// Pipe rewritten documents through a formatter command reading stdin and
+    /// writing stdout, e.g. `rustfmt --edition 2021`. A `LANGUAGE=` prefix
+    /// only uses the command for that language. `{path}` is replaced by the
+    /// file path, and an argument with `{start}` and `{end}` is repeated for
+    /// every range of changed lines to only format those.
+    #[arg(long, value_name = "[LANGUAGE=]COMMAND")]
+    format_with: Vec<String>,
+    #[command(flatten)]
+    walk: WalkOptions,
+}
+
+#[derive(Debug, Args)]
+struct Check {
+    #[command(flatten)]
+    rules: RulesOptions,
+    /// Only report findings with at least this severity (hint, info, warning
+    /// or error).
+    #[arg(long, default_value_t = Severity::Hint)]
+    severity: Severity,
+    /// Baseline file with accepted findings to ignore.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+    /// Record all current findings in a baseline file instead of failing.
+    #[arg(long, value_name = "BASELINE")]
+    write_baseline: Option<PathBuf>,
+    /// Output format of the findings (text, json, sarif or csv).
+    #[arg(long, default_value_t = Format::Text)]
+    format: Format,
+    /// Report `ssr-ignore` comments which did not suppress any finding.
+    #[arg(long)]
+    report_unused_suppressions: bool,
+    /// Keep running and check files again when they change.
+    #[arg(short, long, conflicts_with = "write_baseline")]
+    watch: bool,
     #[command(flatten)]
     walk: WalkOptions,
 }
 
+#[derive(Debug, Args)]
+struct Repl {
+    /// Which language to use.
+    #[arg(short, long)]
+    language: Language,
+    /// File to load into the playground.
+    file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct Lsp {
+    #[command(flatten)]
+    rules: RulesOptions,
+}
+
 impl SsrCommand {
     fn run(&self) -> Result<std::process::ExitCode> {
         match self {
             Self::Tree(cmd) => cmd.run(),
             Self::Search(cmd) => cmd.run(),
             Self::Replace(cmd) => cmd.run(),
+            Self::Check(cmd) => cmd.run(),
+            Self::Repl(cmd) => cmd.run(),
+            Self::Lsp(cmd) => cmd.run(),
         }
     }
 }
@@ -137,82 +783,736 @@ impl SsrCommand {
 impl Tree {
     fn run(&self) -> Result<std::process::ExitCode> {
         let doc = Document::open(&self.file, self.language)?;
-        let mut out = std::io::stdout().lock();
-        doc.write_tree(&mut out)?;
+        if let Some((start, end)) = self.range.filter(|_| self.suggest_query) {
+            let query = doc
+                .suggest_query(start, end)
+                .ok_or("no node covers the range")?;
+            // Make sure the suggestion is a valid query.
+            Query::new(self.language, &query)?;
+            println!("{query}");
+            return Ok(std::process::ExitCode::SUCCESS);
+        }
+        let out = std::io::stdout();
+        let options = TreeOptions {
+            anonymous: self.anonymous,
+            bytes: self.bytes,
+            text: self.text,
+            depth: self.depth,
+            range: self.range,
+            field: self.field.clone(),
+            color: use_color(self.color, &out),
+            format: self.format,
+        };
+        let mut out = out.lock();
+        doc.write_tree_with(&mut out, &options)?;
+        writeln!(out)?;
 
         Ok(std::process::ExitCode::SUCCESS)
     }
 }
 
+/// Output and findings from searching a single file.
+#[derive(Default)]
+struct Found {
+    output: String,
+    matches: usize,
+    reports: Vec<Report>,
+    groups: Groups,
+}
+
+impl Found {
+    /// Output and number of matches, without reports or groups.
+    fn new(output: String, matches: usize) -> Self {
+        Self {
+            output,
+            matches,
+            ..Self::default()
+        }
+    }
+}
+
+const HEADING: &str = "\x1b[1;35m";
+const LINE_NUMBER: &str = "\x1b[32m";
+const HIGHLIGHT: &str = "\x1b[1;31m";
+
+/// Wrap `text` in the ANSI escape `style` if `color` is enabled.
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color && !text.is_empty() {
+        format!("{style}{text}\x1b[0m")
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Check if `m` overlaps the `changed` lines, if any.
+fn touches(changed: Option<&Changes>, doc: &Document, m: &ssr::Match) -> bool {
+    changed.is_none_or(|c| m.range().is_some_and(|r| c.touches(doc.path(), &r)))
+}
+
 impl Search {
     fn run(&self) -> Result<std::process::ExitCode> {
-        let mut found = false;
-        for p in self.walk.walker(self.query.language.as_str())? {
-            let p = p?;
-            let p = p.path();
-            let doc = Document::open(p, self.query.language)?;
-
-            let lw = (doc.lines().count() as f32).log10().floor() as usize;
-
-            for m in doc.find(&self.query.query()?)? {
-                found = true;
-                for c in m.captures() {
-                    println!(
-                        "{}  capture: {} [{}]",
-                        (0..lw).map(|_| ' ').collect::<String>(),
-                        c.name(),
-                        m.pattern_index()
-                    );
-                    for (k, line) in doc
-                        .lines()
-                        .skip(c.start_position().row)
-                        .take(c.end_position().row - c.start_position().row + 1)
-                        .enumerate()
-                    {
-                        println!("{:lw$}: {line}", k + c.start_position().row + 1)
+        let stdin = self.walk.stdin()?;
+        let language = self.query.language(stdin.as_deref())?;
+        let query = self.query.query(language)?;
+        let filter = self.filter.filter()?;
+        let changed = self.walk.changed_lines()?;
+        let script = self.script.as_deref().map(Script::new).transpose()?;
+        let format = self.format.unwrap_or_default();
+        if self.group_by.is_some() && format == Format::Sarif {
+            return Err("--group-by does not support the sarif format".into());
+        }
+        for name in [self.group_by(), self.print.only_capture()]
+            .into_iter()
+            .flatten()
+        {
+            if !query.capture_names().contains(&name) {
+                return Err(format!("query has no capture `@{name}`").into());
+            }
+        }
+        let search = |doc: &Document| {
+            let changed = changed.as_ref();
+            self.search(doc, &query, filter.as_ref(), script.as_ref(), changed)
+        };
+
+        if self.watch {
+            return self.walk.watch(
+                &[language],
+                |p, _| match self.walk.open(p, language, &mut Skipped::default())? {
+                    Some(doc) => search(&doc),
+                    None => Ok(Found::default()),
+                },
+                |results, updated| {
+                    let mut out = std::io::stdout().lock();
+                    if format == Format::Text {
+                        for f in updated.iter().filter_map(|k| results.get(k)) {
+                            out.write_all(f.output.as_bytes())?;
+                            ssr::write_reports(&mut out, format, &f.reports)?;
+                        }
+                    } else {
+                        let reports = results.values().flat_map(|f| f.reports.iter());
+                        ssr::write_reports(&mut out, format, reports)?;
                     }
-                }
-                println!();
+                    Ok(())
+                },
+            );
+        }
+
+        let mut found = false;
+        let mut reports = Vec::new();
+        let mut groups = Groups::default();
+        let mut skipped = Skipped::default();
+        let mut failures = Failures::new(self.walk.keep_going);
+        let selected = match stdin {
+            Some(_) => None,
+            None => self.walk.selected()?,
+        };
+        let selected = selected.as_deref();
+        let docs: Box<dyn Iterator<Item = Input>> = match stdin {
+            Some(path) => Box::new(std::iter::once((
+                Some(path.clone()),
+                self.walk.read_stdin(path, language).map(Some),
+            ))),
+            None => Box::new(
+                self.walk
+                    .walker(language.as_str(), selected)?
+                    .map(|p| match p {
+                        Ok(p) => {
+                            let doc = self.walk.open(p.path(), language, &mut skipped);
+                            (Some(p.into_path()), doc)
+                        }
+                        Err(e) => (None, Err(e.into())),
+                    }),
+            ),
+        };
+        for (path, doc) in docs {
+            let Some(Some(doc)) = failures.check(path.as_deref(), doc)? else {
+                continue;
+            };
+            let Some(f) = failures.check(Some(doc.path()), search(&doc))? else {
+                continue;
+            };
+            found |= f.matches > 0 || !f.reports.is_empty();
+            if self.quiet && found {
+                break;
             }
+            print!("{}", f.output);
+            reports.extend(f.reports);
+            groups.extend(f.groups);
+        }
+        if script.is_some() {
+            let out = std::io::stdout().lock();
+            ssr::write_reports(out, format, &reports)?;
         }
-        Ok(if found {
+        if self.group_by.is_some() {
+            groups.write(std::io::stdout().lock(), format)?;
+        }
+        skipped.summary();
+        Ok(failures.report(if found {
             std::process::ExitCode::SUCCESS
         } else {
             std::process::ExitCode::FAILURE
-        })
+        }))
+    }
+
+    fn group_by(&self) -> Option<&str> {
+        let name = self.group_by.as_deref()?;
+        Some(name.strip_prefix('@').unwrap_or(name))
+    }
+
+    fn search(
+        &self,
+        doc: &Document,
+        query: &Query,
+        filter: Option<&Filter>,
+        script: Option<&Script>,
+        changed: Option<&Changes>,
+    ) -> Result<Found> {
+        let path = doc.path();
+        let mut matches = Vec::new();
+        for m in doc.find(query)? {
+            if !touches(changed, doc, &m) {
+                continue;
+            }
+            if let Some(filter) = filter {
+                if !filter.is_match(doc, &m)? {
+                    continue;
+                }
+            }
+            matches.push(m);
+            if self.max_count.is_some_and(|n| matches.len() >= n) {
+                break;
+            }
+        }
+
+        let mut output = String::new();
+        let count = matches.len();
+        if let Some(script) = script {
+            let reports = script.run(doc, matches)?.reports().to_vec();
+            return Ok(Found {
+                reports,
+                ..Found::new(output, count)
+            });
+        }
+        if let Some(name) = self.group_by() {
+            let mut groups = Groups::default();
+            for c in matches.iter().flat_map(|m| m.captures()) {
+                if c.name() == name {
+                    groups.add(path, &c);
+                }
+            }
+            return Ok(Found {
+                groups,
+                ..Found::new(output, count)
+            });
+        }
+        if self.quiet {
+            return Ok(Found::new(output, count));
+        }
+        if self.count {
+            writeln!(output, "{}:{count}", path.display())?;
+        }
+        if (self.files_with_matches && count > 0) || (self.files_without_match && count == 0) {
+            writeln!(output, "{}", path.display())?;
+        }
+        if self.count || self.files_with_matches || self.files_without_match {
+            return Ok(Found::new(output, count));
+        }
+
+        let lines = doc.lines().collect::<Vec<_>>();
+        let lw = (lines.len() as f32).log10().floor() as usize;
+        let color = self.print.color();
+        let (before, after) = (self.print.before(), self.print.after());
+
+        for m in matches {
+            let captures = m
+                .captures()
+                .filter(|c| self.print.only_capture().is_none_or(|n| c.name() == n))
+                .collect::<Vec<_>>();
+            if captures.is_empty() {
+                continue;
+            }
+            if output.is_empty() {
+                writeln!(
+                    output,
+                    "{}",
+                    paint(&path.display().to_string(), HEADING, color)
+                )?;
+            }
+            for c in captures {
+                writeln!(
+                    output,
+                    "{}  capture: {} [{}]",
+                    (0..lw).map(|_| ' ').collect::<String>(),
+                    c.name(),
+                    m.pattern_index()
+                )?;
+                let (start, end) = (c.start_position(), c.end_position());
+                let first = start.row.saturating_sub(before);
+                let last = (end.row + after).min(lines.len().saturating_sub(1));
+                for (row, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+                    let number = format!("{:lw$}", row + 1);
+                    if !(start.row..=end.row).contains(&row) {
+                        writeln!(output, "{}- {line}", paint(&number, LINE_NUMBER, color))?;
+                        continue;
+                    }
+                    let from = if row == start.row { start.column } else { 0 };
+                    let to = if row == end.row {
+                        end.column
+                    } else {
+                        line.len()
+                    };
+                    let line = match (line.get(..from), line.get(from..to), line.get(to..)) {
+                        (Some(a), Some(b), Some(c)) if color => {
+                            format!("{a}{}{c}", paint(b, HIGHLIGHT, color))
+                        }
+                        _ => line.to_string(),
+                    };
+                    writeln!(output, "{}: {line}", paint(&number, LINE_NUMBER, color))?;
+                }
+            }
+            writeln!(output)?;
+        }
+        Ok(Found::new(output, count))
     }
 }
 
 impl Replace {
     fn run(&self) -> Result<std::process::ExitCode> {
-        let mut changed = false;
-        for p in self.walk.walker(self.query.language.as_str())? {
-            let p = match p {
-                Ok(p) => p,
-                Err(e) => {
-                    eprintln!("failed to process: {e}");
-                    continue;
+        let mut failures = Failures::new(self.walk.keep_going);
+        let stdin = self.walk.stdin()?;
+        let language = self.query.language(stdin.as_deref())?;
+        let query = self.query.query(language)?;
+        let workspace = Workspace::new(language, self.walk.open_options());
+        let mut script = Script::new(&self.replacement)?.with_workspace(workspace.clone());
+        let lines = self.walk.changed_lines()?;
+        let lines = lines.as_ref();
+        // Changes are printed at the end, when the changes made to other files
+        // through the workspace are known.
+        let mut edited = Vec::new();
+        if let Some(path) = stdin {
+            let doc = self.walk.read_stdin(path, language)?;
+            if script.has_collect() {
+                let mut facts = Facts::default();
+                let matches = doc.find(&query)?.filter(|m| touches(lines, &doc, m));
+                script.collect(&doc, matches, &mut facts)?;
+                script = script.with_facts(facts);
+            }
+            let changes = self.replace(&doc, &query, &script, lines)?;
+            if self.content {
+                let content = doc.file_content().into_owned();
+                let mut changes = workspace.changes([(doc, changes)])?;
+                if changes.len() > 1 || changes.iter().any(|c| c.original().is_none()) {
+                    return Err("--content can not print changes to other files".into());
                 }
-            };
-            let p = p.path();
-            let doc = Document::open(p, self.query.language)?;
-            let new = doc.edit(&self.query.source, &self.replacement)?;
-            let patch = doc.diff(&new);
-            if patch.is_changed() {
-                changed = true;
-                println!("{}", &patch);
+                match changes.pop() {
+                    Some(change) => print!("{}", self.format(change)?.document().file_content()),
+                    None => print!("{content}"),
+                }
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            edited.push((doc, changes));
+        } else if self.content {
+            return Err("--content requires reading the document from stdin".into());
+        } else {
+            let selected = self.walk.selected()?;
+            let selected = selected.as_deref();
+            if script.has_collect() {
+                let mut facts = Facts::default();
+                // Skipped files are counted when rewriting.
+                let mut skipped = Skipped::default();
+                self.documents(language, selected, &mut failures, &mut skipped, |doc| {
+                    let matches = doc.find(&query)?.filter(|m| touches(lines, &doc, m));
+                    Ok(script.collect(&doc, matches, &mut facts)?)
+                })?;
+                script = script.with_facts(facts);
             }
+            let mut skipped = Skipped::default();
+            self.documents(language, selected, &mut failures, &mut skipped, |doc| {
+                let changes = self.replace(&doc, &query, &script, lines)?;
+                if !changes.is_empty() && doc.is_decoded() {
+                    // Fail early for this file only, see `Workspace::changes`.
+                    return Err(WorkspaceError::Decoded(doc.path().to_owned()).into());
+                }
+                if !changes.is_empty() {
+                    edited.push((doc, changes));
+                }
+                Ok(())
+            })?;
+            skipped.summary();
         }
-        Ok(if changed {
+
+        let mut changed = false;
+        for change in workspace.changes(edited)? {
+            let path = change.document().path().to_owned();
+            let Some(change) = failures.check(Some(&path), self.format(change))? else {
+                continue;
+            };
+            println!("{change}");
+            changed = true;
+        }
+        Ok(failures.report(if changed {
             std::process::ExitCode::SUCCESS
         } else {
             std::process::ExitCode::FAILURE
-        })
+        }))
+    }
+
+    /// Call `f` for every walked document, errors of a file are handled by
+    /// `failures` and files which failed before are skipped.
+    fn documents(
+        &self,
+        language: Language,
+        selected: Option<&[PathBuf]>,
+        failures: &mut Failures,
+        skipped: &mut Skipped,
+        mut f: impl FnMut(Document) -> Result<()>,
+    ) -> Result<()> {
+        for p in self.walk.walker(language.as_str(), selected)? {
+            let Some(p) = failures.check(None, p.map_err(Error::from))? else {
+                continue;
+            };
+            if failures.has_failed(p.path()) {
+                continue;
+            }
+            let doc = self.walk.open(p.path(), language, skipped);
+            let Some(Some(doc)) = failures.check(Some(p.path()), doc)? else {
+                continue;
+            };
+            failures.check(Some(p.path()), f(doc))?;
+        }
+        Ok(())
+    }
+
+    /// Run the script for all matches in `doc` and return the edits.
+    fn replace(
+        &self,
+        doc: &Document,
+        query: &Query,
+        script: &Script,
+        changed: Option<&Changes>,
+    ) -> Result<Vec<Change>> {
+        let matches = doc.find(query)?.filter(|m| touches(changed, doc, m));
+        let output = script.run(doc, matches)?;
+        ssr::write_reports(std::io::stderr().lock(), Format::Text, output.reports())?;
+        let changes = output.changes().iter().cloned();
+        Ok(changes
+            .map(|c| {
+                let reindent = self.reindent || c.is_reindent();
+                c.with_reindent(reindent)
+            })
+            .collect())
+    }
+
+    /// The formatter for `language`, a command with a language prefix takes
+    /// precedence over one without.
+    fn formatter(&self, language: Language) -> Result<Option<Formatter>> {
+        let mut fallback = None;
+        for command in &self.format_with {
+            let prefix = command
+                .split_once('=')
+                .filter(|(l, _)| !l.contains(char::is_whitespace))
+                .and_then(|(l, c)| Some((l.parse::<Language>().ok()?, c)));
+            match prefix {
+                Some((l, c)) if l == language => return Ok(Some(Formatter::parse(c)?)),
+                Some(_) => {}
+                None => fallback = fallback.or(Some(command.as_str())),
+            }
+        }
+        Ok(fallback.map(Formatter::parse).transpose()?)
+    }
+
+    /// Format the changed document with the formatter for its language.
+    fn format(&self, change: FileChange) -> Result<FileChange> {
+        let Some(formatter) = self.formatter(change.document().language())? else {
+            return Ok(change);
+        };
+        let new = formatter.format(change.document(), &change.changed_lines())?;
+        Ok(change.with_new(new))
     }
 }
 
-fn main() -> Result<std::process::ExitCode> {
+impl Check {
+    fn run(&self) -> Result<std::process::ExitCode> {
+        let rules = self.rules.rules()?;
+        let changed = self.walk.changed_lines()?;
+        let changed = changed.as_ref();
+        let baseline = self.baseline.as_ref().map(Baseline::open).transpose()?;
+        let languages = rules.languages().into_iter().collect::<Vec<_>>();
+
+        if self.watch {
+            return self.walk.watch(
+                &languages,
+                |p, language| {
+                    let Some(doc) = self.walk.open(p, language, &mut Skipped::default())? else {
+                        return Ok(Vec::new());
+                    };
+                    let reports = self.check(&rules, &doc, changed)?;
+                    Ok(match &baseline {
+                        Some(baseline) => baseline.filter(reports),
+                        None => reports,
+                    })
+                },
+                |results, updated| {
+                    let mut out = std::io::stdout().lock();
+                    if self.format == Format::Text {
+                        let reports = updated.iter().filter_map(|k| results.get(k)).flatten();
+                        ssr::write_reports(&mut out, self.format, reports)?;
+                    } else {
+                        ssr::write_reports(&mut out, self.format, results.values().flatten())?;
+                    }
+                    self.summary(&rules, results.values().flatten());
+                    Ok(())
+                },
+            );
+        }
+
+        let mut reports = Vec::new();
+        let mut skipped = Skipped::default();
+        let mut failures = Failures::new(self.walk.keep_going);
+        if let Some(path) = self.walk.stdin()? {
+            let language = Language::from_path(&path)
+                .ok_or("unknown language, use --stdin-filename with a known extension")?;
+            let doc = self.walk.read_stdin(path, language)?;
+            reports.extend(self.check(&rules, &doc, changed)?);
+        } else {
+            // Resolved once, `--files-from -` can only read stdin once.
+            let selected = self.walk.selected()?;
+            for &language in languages.iter() {
+                for p in self.walk.walker(language.as_str(), selected.as_deref())? {
+                    let Some(p) = failures.check(None, p.map_err(Error::from))? else {
+                        continue;
+                    };
+                    let doc = self.walk.open(p.path(), language, &mut skipped);
+                    let Some(Some(doc)) = failures.check(Some(p.path()), doc)? else {
+                        continue;
+                    };
+                    let found = self.check(&rules, &doc, changed);
+                    if let Some(found) = failures.check(Some(p.path()), found)? {
+                        reports.extend(found);
+                    }
+                }
+            }
+        }
+        reports.sort_by(|a, b| {
+            (a.path(), a.range().start_byte, a.rule()).cmp(&(
+                b.path(),
+                b.range().start_byte,
+                b.rule(),
+            ))
+        });
+
+        if let Some(path) = &self.write_baseline {
+            let baseline = Baseline::from_reports(&reports);
+            baseline.write(path)?;
+            eprintln!("wrote {} finding(s) to {}", baseline.len(), path.display());
+            return Ok(failures.report(std::process::ExitCode::SUCCESS));
+        }
+        if let Some(baseline) = &baseline {
+            reports = baseline.filter(reports);
+        }
+
+        ssr::write_reports(std::io::stdout().lock(), self.format, &reports)?;
+        self.summary(&rules, &reports);
+        skipped.summary();
+
+        Ok(failures.report(if reports.is_empty() {
+            std::process::ExitCode::SUCCESS
+        } else {
+            std::process::ExitCode::from(1)
+        }))
+    }
+
+    /// Findings of all rules in a single file, sorted by position.
+    fn check(
+        &self,
+        rules: &Rules,
+        doc: &Document,
+        changed: Option<&Changes>,
+    ) -> Result<Vec<Report>> {
+        let mut suppressions = Suppressions::new(doc);
+        let mut reports = Vec::new();
+        for rule in rules.for_language(doc.language()) {
+            reports.extend(
+                rule.check(doc)?
+                    .into_iter()
+                    .filter(|r| !suppressions.suppresses(r))
+                    .filter(|r| r.severity() >= self.severity),
+            );
+        }
+        if self.report_unused_suppressions {
+            reports.extend(
+                suppressions
+                    .unused(doc)
+                    .into_iter()
+                    .filter(|r| r.severity() >= self.severity),
+            );
+        }
+        if let Some(changed) = changed {
+            reports.retain(|r| changed.touches(r.path(), r.range()));
+        }
+        reports.sort_by(|a, b| {
+            (a.range().start_byte, a.rule()).cmp(&(b.range().start_byte, b.rule()))
+        });
+        Ok(reports)
+    }
+
+    /// Print the number of findings per rule to stderr.
+    fn summary<'a>(&self, rules: &Rules, reports: impl IntoIterator<Item = &'a Report>) {
+        let mut summary = rules
+            .iter()
+            .map(|r| (r.id(), 0))
+            .collect::<BTreeMap<_, usize>>();
+        if self.report_unused_suppressions {
+            summary.insert(ssr::UNUSED_SUPPRESSION, 0);
+        }
+        let mut total = 0;
+        for r in reports {
+            total += 1;
+            if let Some(n) = r.rule().and_then(|id| summary.get_mut(id)) {
+                *n += 1;
+            }
+        }
+        for (id, n) in summary {
+            eprintln!("{id}: {n} finding(s)");
+        }
+        eprintln!("total: {total} finding(s)");
+    }
+}
+
+impl Repl {
+    fn run(&self) -> Result<std::process::ExitCode> {
+        let doc = Document::open(&self.file, self.language)?;
+        let input = std::io::stdin().lock();
+        let out = std::io::stdout();
+        let color = out.is_terminal();
+        ssr::repl::run(&doc, input, out.lock(), color)?;
+
+        Ok(std::process::ExitCode::SUCCESS)
+    }
+}
+
+impl Lsp {
+    fn run(&self) -> Result<std::process::ExitCode> {
+        let rules = self.rules.rules()?;
+        ssr::lsp::serve(rules)?;
+
+        Ok(std::process::ExitCode::SUCCESS)
+    }
+}
+
+fn main() -> std::process::ExitCode {
     let options = Options::parse();
 
-    options.command.run()
+    match options.command.run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `ssr replace` with `args` on a missing path, an error of the walker.
+    fn replace_missing(args: &[&str]) -> (Result<()>, Failures) {
+        let mut argv = vec![
+            "ssr",
+            "replace",
+            "-l",
+            "rust",
+            "-q",
+            "(identifier)",
+            "-r",
+            "",
+        ];
+        argv.extend(args);
+        argv.push("/ssr/no/such/dir");
+        let Ok(Options {
+            command: SsrCommand::Replace(replace),
+        }) = Options::try_parse_from(argv)
+        else {
+            panic!("invalid arguments");
+        };
+        let mut failures = Failures::new(replace.walk.keep_going);
+        let result = replace.documents(
+            Language::Rust,
+            None,
+            &mut failures,
+            &mut Skipped::default(),
+            |_| Ok(()),
+        );
+        (result, failures)
+    }
+
+    #[test]
+    fn walker_errors() {
+        let (result, failures) = replace_missing(&[]);
+        assert!(result.is_err());
+        assert!(failures.errors.is_empty());
+
+        let (result, failures) = replace_missing(&["--keep-going"]);
+        assert!(result.is_ok());
+        assert_eq!(failures.errors.values().map(Vec::len).sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn check_exit_codes() {
+        let dir = std::env::temp_dir().join(format!("ssr-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("ssr.toml");
+        let rule = "[[rule]]\nid = \"no-print\"\nlanguage = \"python\"\n\
+                    query = '((call function: (identifier) @f) (#eq? @f \"print\"))'\n";
+        std::fs::write(&rules, rule).unwrap();
+        std::fs::write(dir.join("a.py"), "print(1)\n").unwrap();
+
+        let check = |args: &[&str]| {
+            let rules = rules.to_str().unwrap();
+            let mut argv = vec!["ssr", "check", "--rules", rules, "--format", "json"];
+            argv.extend(args);
+            argv.push(dir.to_str().unwrap());
+            let Ok(Options {
+                command: SsrCommand::Check(check),
+            }) = Options::try_parse_from(argv)
+            else {
+                panic!("invalid arguments");
+            };
+            check.run().map_err(|e| e.to_string())
+        };
+        assert_eq!(check(&[]), Ok(std::process::ExitCode::from(1)));
+        // Rules are warnings by default.
+        assert_eq!(
+            check(&["--severity", "warning"]),
+            Ok(std::process::ExitCode::from(1))
+        );
+        assert_eq!(
+            check(&["--severity", "error"]),
+            Ok(std::process::ExitCode::SUCCESS)
+        );
+
+        // Errors stop the run or, with `--keep-going`, exit with 2.
+        std::fs::write(&rules, format!("{rule}script = 'throw \"x\"'\n")).unwrap();
+        assert!(check(&[]).is_err());
+        assert_eq!(
+            check(&["--keep-going"]),
+            Ok(std::process::ExitCode::from(2))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("4K"), Ok(4 << 10));
+        assert_eq!(parse_size("2m"), Ok(2 << 20));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
 }
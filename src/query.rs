@@ -32,4 +32,8 @@ impl Query {
     pub fn capture_name(&self, index: u32) -> &str {
         self.query.capture_names()[index as usize]
     }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
 }
@@ -0,0 +1,239 @@
+//
+// Interactive playground to develop queries and scripts against a document.
+//
+use crate::{Capture, Document, Format, Query, Script};
+use std::io::{BufRead, Write};
+
+const PROMPT: &str = "ssr> ";
+const CONTINUE: &str = "...> ";
+
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+const HELP: &str = "\
+Enter a query to show its matches, queries can span multiple lines and an
+empty line submits an incomplete query.
+
+Commands:
+  :tree               show the tree of the document
+  :node ROW:COL       show the smallest node at a point and its ancestors
+  :script [SCRIPT]    run a script for the matches of the last query and show
+                      the resulting patch, without SCRIPT it is read from the
+                      following lines until an empty line
+  :help               show this help
+  :quit               leave the playground
+
+Rows and columns are zero based as in the output of :tree.
+";
+
+/// Run the playground for `doc`, reading from `input` until it ends or
+/// `:quit` is entered.
+pub fn run(
+    doc: &Document,
+    mut input: impl BufRead,
+    mut out: impl Write,
+    color: bool,
+) -> std::io::Result<()> {
+    let mut repl = Repl {
+        doc,
+        color,
+        query: None,
+    };
+    let mut pending = String::new();
+    loop {
+        out.write_all(if pending.is_empty() { PROMPT } else { CONTINUE }.as_bytes())?;
+        out.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(out)?;
+            return Ok(());
+        }
+
+        if pending.is_empty() {
+            if let Some(command) = line.trim().strip_prefix(':') {
+                let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+                match name {
+                    "q" | "quit" => return Ok(()),
+                    "h" | "help" => out.write_all(HELP.as_bytes())?,
+                    "tree" => {
+                        doc.write_tree(&mut out)?;
+                        writeln!(out)?;
+                    }
+                    "node" => repl.node(&mut out, arg.trim())?,
+                    "script" => {
+                        let mut script = arg.trim().to_owned();
+                        if script.is_empty() {
+                            loop {
+                                out.write_all(CONTINUE.as_bytes())?;
+                                out.flush()?;
+                                let mut line = String::new();
+                                if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                                    break;
+                                }
+                                script.push_str(&line);
+                            }
+                        }
+                        repl.script(&mut out, &script)?;
+                    }
+                    _ => writeln!(out, "unknown command `:{name}`, try :help")?,
+                }
+                continue;
+            }
+        }
+
+        // An empty line submits an incomplete query to show what is wrong.
+        let submit = line.trim().is_empty();
+        pending.push_str(&line);
+        if pending.trim().is_empty() {
+            pending.clear();
+        } else if submit || is_complete(&pending) {
+            repl.query(&mut out, &pending)?;
+            pending.clear();
+        }
+    }
+}
+
+struct Repl<'a> {
+    doc: &'a Document,
+    color: bool,
+    query: Option<Query>,
+}
+
+impl Repl<'_> {
+    fn query(&mut self, out: &mut impl Write, source: &str) -> std::io::Result<()> {
+        let query = match Query::new(self.doc.language(), source) {
+            Ok(query) => query,
+            Err(e) => return writeln!(out, "error: {e}"),
+        };
+        let matches = match self.doc.find(&query) {
+            Ok(matches) => matches.collect::<Vec<_>>(),
+            Err(e) => return writeln!(out, "error: {e}"),
+        };
+        for (k, m) in matches.iter().enumerate() {
+            writeln!(out, "match {} (pattern {})", k + 1, m.pattern_index())?;
+            for c in m.captures() {
+                self.capture(out, &c)?;
+            }
+        }
+        writeln!(out, "{} match(es)", matches.len())?;
+        self.query = Some(query);
+        Ok(())
+    }
+
+    /// Print the lines of a capture with the captured text highlighted.
+    fn capture(&self, out: &mut impl Write, c: &Capture) -> std::io::Result<()> {
+        let start = c.start_position();
+        let end = c.end_position();
+        writeln!(
+            out,
+            "  @{} [{}, {}] - [{}, {}]",
+            c.name(),
+            start.row,
+            start.column,
+            end.row,
+            end.column
+        )?;
+        for (row, line) in self
+            .doc
+            .lines()
+            .enumerate()
+            .skip(start.row)
+            .take(end.row - start.row + 1)
+        {
+            let from = if row == start.row { start.column } else { 0 };
+            let to = if row == end.row {
+                end.column
+            } else {
+                line.len()
+            };
+            let (from, to) = (from.min(line.len()), to.min(line.len()).max(from));
+            let (Some(before), Some(text), Some(after)) =
+                (line.get(..from), line.get(from..to), line.get(to..))
+            else {
+                writeln!(out, "  {row:4} | {line}")?;
+                continue;
+            };
+            if self.color {
+                writeln!(out, "  {row:4} | {before}{HIGHLIGHT}{text}{RESET}{after}")?;
+            } else {
+                writeln!(out, "  {row:4} | {line}")?;
+                writeln!(
+                    out,
+                    "       | {}{}",
+                    " ".repeat(before.chars().count()),
+                    "^".repeat(text.chars().count())
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&self, out: &mut impl Write, arg: &str) -> std::io::Result<()> {
+        let point = arg
+            .split_once(':')
+            .and_then(|(row, column)| Some((row.parse().ok()?, column.parse().ok()?)));
+        let Some((row, column)) = point else {
+            return writeln!(out, "error: expected ROW:COL, got `{arg}`");
+        };
+        self.doc
+            .write_ancestors(out, tree_sitter::Point { row, column })?;
+        writeln!(out)
+    }
+
+    fn script(&self, out: &mut impl Write, source: &str) -> std::io::Result<()> {
+        let Some(query) = &self.query else {
+            return writeln!(out, "error: enter a query first");
+        };
+        let output = Script::new(source)
+            .and_then(|script| script.run(self.doc, self.doc.find(query)?))
+            .and_then(|output| {
+                let new = self.doc.apply_edits(output.changes().iter().cloned())?;
+                Ok((output, new))
+            });
+        let (output, new) = match output {
+            Ok(output) => output,
+            Err(e) => return writeln!(out, "error: {e}"),
+        };
+        crate::write_reports(&mut *out, Format::Text, output.reports())?;
+        let patch = self.doc.diff(&new);
+        if patch.is_changed() {
+            write!(out, "{patch}")?;
+        } else {
+            writeln!(out, "no changes")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if all parentheses and brackets of a query are closed, ignoring
+/// string literals and comments.
+fn is_complete(source: &str) -> bool {
+    let mut depth = 0i32;
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    depth <= 0
+}
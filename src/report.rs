@@ -0,0 +1,328 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Severity of a finding reported by a script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct SeverityError;
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hint => "hint",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+
+    /// Name of the corresponding SARIF `level`.
+    fn sarif_level(&self) -> &'static str {
+        match self {
+            Self::Hint => "none",
+            Self::Info => "note",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl Display for SeverityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid severity")
+    }
+}
+
+impl std::error::Error for SeverityError {}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Severity {
+    type Err = SeverityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let ret = match s.as_str() {
+            "hint" => Self::Hint,
+            "info" | "note" => Self::Info,
+            "warning" | "warn" => Self::Warning,
+            "error" => Self::Error,
+            _ => return Err(SeverityError),
+        };
+        Ok(ret)
+    }
+}
+
+/// A finding reported by a script via `document.report(range, message, severity)`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Report {
+    path: PathBuf,
+    rule: Option<String>,
+    fingerprint: Option<String>,
+    range: tree_sitter::Range,
+    message: String,
+    severity: Severity,
+}
+
+impl Report {
+    pub fn new(
+        path: PathBuf,
+        range: tree_sitter::Range,
+        message: String,
+        severity: Severity,
+    ) -> Self {
+        Self {
+            path,
+            rule: None,
+            fingerprint: None,
+            range,
+            message,
+            severity,
+        }
+    }
+    /// Attribute this report to the rule with the given id.
+    pub fn with_rule(mut self, rule: impl Into<String>) -> Self {
+        self.rule = Some(rule.into());
+        self
+    }
+    /// Set a stable identifier of the reported code, see [`crate::Baseline`].
+    pub fn with_fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
+        self.fingerprint = Some(fingerprint.into());
+        self
+    }
+    /// Look up the bytes of a range built from lines and columns in `doc`,
+    /// like [`crate::Change`], `None` if it is not in the document.
+    pub(crate) fn locate(mut self, doc: &crate::Document) -> Option<Self> {
+        if self.range.start_byte == crate::doc::UNLOCATED {
+            self.range = doc.locate(self.range.start_point, self.range.end_point)?;
+        }
+        Some(self)
+    }
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+    pub fn rule(&self) -> Option<&str> {
+        self.rule.as_deref()
+    }
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+    pub fn range(&self) -> &tree_sitter::Range {
+        &self.range
+    }
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path.display().to_string(),
+            "rule": self.rule,
+            "fingerprint": self.fingerprint,
+            "severity": self.severity.as_str(),
+            "message": self.message,
+            "range": {
+                "start": {
+                    "row": self.range.start_point.row,
+                    "column": self.range.start_point.column,
+                    "byte": self.range.start_byte,
+                },
+                "end": {
+                    "row": self.range.end_point.row,
+                    "column": self.range.end_point.column,
+                    "byte": self.range.end_byte,
+                },
+            },
+        })
+    }
+
+    fn to_sarif(&self) -> serde_json::Value {
+        // SARIF lines and columns are 1-based.
+        let mut result = serde_json::json!({
+            "level": self.severity.sarif_level(),
+            "message": { "text": self.message },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": self.path.display().to_string() },
+                    "region": {
+                        "startLine": self.range.start_point.row + 1,
+                        "startColumn": self.range.start_point.column + 1,
+                        "endLine": self.range.end_point.row + 1,
+                        "endColumn": self.range.end_point.column + 1,
+                    },
+                },
+            }],
+        });
+        if let Some(rule) = &self.rule {
+            result["ruleId"] = rule.as_str().into();
+        }
+        if let Some(fingerprint) = &self.fingerprint {
+            result["partialFingerprints"] = serde_json::json!({ "ssr/v1": fingerprint });
+        }
+        result
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path.display(),
+            self.range.start_point.row + 1,
+            self.range.start_point.column + 1,
+            self.severity,
+        )?;
+        if let Some(rule) = &self.rule {
+            write!(f, "[{rule}]")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Output format for reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Sarif,
+    Csv,
+}
+
+#[derive(Debug)]
+pub struct FormatError;
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Sarif => "sarif",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid format")
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Format {
+    type Err = FormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let ret = match s.as_str() {
+            "text" => Self::Text,
+            "json" => Self::Json,
+            "sarif" => Self::Sarif,
+            "csv" => Self::Csv,
+            _ => return Err(FormatError),
+        };
+        Ok(ret)
+    }
+}
+
+/// Write `reports` to `out` in the given `format`.
+pub fn write_reports<'a>(
+    mut out: impl std::io::Write,
+    format: Format,
+    reports: impl IntoIterator<Item = &'a Report>,
+) -> std::io::Result<()> {
+    match format {
+        Format::Text => {
+            for r in reports {
+                writeln!(out, "{r}")?;
+            }
+        }
+        Format::Json => {
+            let reports = reports.into_iter().map(Report::to_json).collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut out, &reports)?;
+            writeln!(out)?;
+        }
+        Format::Sarif => {
+            let reports = reports.into_iter().collect::<Vec<_>>();
+            let rules = reports
+                .iter()
+                .filter_map(|r| r.rule())
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .map(|id| serde_json::json!({ "id": id }))
+                .collect::<Vec<_>>();
+            let results = reports
+                .into_iter()
+                .map(Report::to_sarif)
+                .collect::<Vec<_>>();
+            let log = serde_json::json!({
+                "version": "2.1.0",
+                "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+                "runs": [{
+                    "tool": {
+                        "driver": {
+                            "name": env!("CARGO_PKG_NAME"),
+                            "version": env!("CARGO_PKG_VERSION"),
+                            "informationUri": "https://github.com/kiron1/ssr",
+                            "rules": rules,
+                        },
+                    },
+                    "results": results,
+                }],
+            });
+            serde_json::to_writer_pretty(&mut out, &log)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(out, "path,line,column,severity,rule,message")?;
+            for r in reports {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    csv_field(&r.path.display().to_string()),
+                    r.range.start_point.row + 1,
+                    r.range.start_point.column + 1,
+                    r.severity,
+                    csv_field(r.rule().unwrap_or_default()),
+                    csv_field(&r.message),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if needed, see RFC 4180.
+pub(crate) fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
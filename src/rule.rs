@@ -0,0 +1,355 @@
+use crate::{
+    baseline::fingerprint, Change, Document, Filter, Language, Match, Query, Report, Script,
+    Severity,
+};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("I/O error in {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("Invalid rules file {0}: {1}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+    #[error("Invalid language `{1}` in rule {0}")]
+    Language(String, String),
+    #[error("Invalid severity `{1}` in rule {0}")]
+    Severity(String, String),
+    #[error("Duplicate rule {0}")]
+    Duplicate(String),
+    #[error("Query error in rule {0}: {1}")]
+    Query(String, #[source] crate::query::Error),
+    #[error("Script error in rule {0}: {1}")]
+    Script(String, #[source] crate::doc::Error),
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    id: String,
+    language: String,
+    query: String,
+    message: Option<String>,
+    severity: Option<String>,
+    filter: Option<String>,
+    script: Option<String>,
+}
+
+/// A named query with an optional filter and script which produces findings.
+pub struct Rule {
+    id: String,
+    language: Language,
+    query: Query,
+    message: String,
+    severity: Severity,
+    filter: Option<Filter>,
+    script: Option<Script>,
+}
+
+impl Rule {
+    fn new(config: RuleConfig) -> Result<Self> {
+        let RuleConfig {
+            id,
+            language,
+            query,
+            message,
+            severity,
+            filter,
+            script,
+        } = config;
+        let language = language
+            .parse::<Language>()
+            .map_err(|_| Error::Language(id.to_owned(), language))?;
+        let severity = match severity {
+            Some(s) => s
+                .parse::<Severity>()
+                .map_err(|_| Error::Severity(id.to_owned(), s))?,
+            None => Severity::Warning,
+        };
+        let query = Query::new(language, &query).map_err(|e| Error::Query(id.to_owned(), e))?;
+        let filter = filter
+            .as_deref()
+            .map(Filter::new)
+            .transpose()
+            .map_err(|e| Error::Script(id.to_owned(), e))?;
+        let script = script
+            .as_deref()
+            .map(Script::new)
+            .transpose()
+            .map_err(|e| Error::Script(id.to_owned(), e))?;
+        let message = message.unwrap_or_else(|| id.to_owned());
+        Ok(Self {
+            id,
+            language,
+            query,
+            message,
+            severity,
+            filter,
+            script,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        self.id.as_str()
+    }
+    pub fn language(&self) -> Language {
+        self.language
+    }
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Apply this rule to `doc`.
+    ///
+    /// Without a script every match is reported with the rule's message and
+    /// severity, otherwise the findings reported by the script are returned.
+    pub fn check(&self, doc: &Document) -> std::result::Result<Vec<Report>, crate::doc::Error> {
+        let matches = self.matches(doc)?;
+
+        let reports = if let Some(script) = &self.script {
+            script
+                .run(doc, matches)?
+                .reports()
+                .iter()
+                .map(|r| {
+                    let text = doc
+                        .content()
+                        .get(r.range().start_byte..r.range().end_byte)
+                        .unwrap_or_default();
+                    let fingerprint = fingerprint([self.id.as_str(), text]);
+                    r.to_owned().with_fingerprint(fingerprint)
+                })
+                .collect()
+        } else {
+            matches
+                .iter()
+                .filter_map(|m| {
+                    let range = m.range()?;
+                    let captures = m.captures().collect::<Vec<_>>();
+                    let fingerprint = fingerprint(
+                        std::iter::once(self.id.as_str())
+                            .chain(captures.iter().flat_map(|c| [c.name(), c.text()])),
+                    );
+                    let report = Report::new(
+                        doc.path().to_owned(),
+                        range,
+                        self.message.to_owned(),
+                        self.severity,
+                    );
+                    Some(report.with_fingerprint(fingerprint))
+                })
+                .collect::<Vec<_>>()
+        };
+        Ok(reports
+            .into_iter()
+            .map(|r| r.with_rule(self.id.as_str()))
+            .collect())
+    }
+
+    /// Changes of the rule's script for all matches which overlap `range`.
+    pub fn fix(
+        &self,
+        doc: &Document,
+        range: &tree_sitter::Range,
+    ) -> std::result::Result<Vec<Change>, crate::doc::Error> {
+        let Some(script) = &self.script else {
+            return Ok(Vec::new());
+        };
+        let matches = self.matches(doc)?.into_iter().filter(|m| {
+            m.range()
+                .is_some_and(|r| r.start_byte <= range.end_byte && range.start_byte <= r.end_byte)
+        });
+        Ok(script.run(doc, matches)?.changes().to_vec())
+    }
+
+    fn matches(&self, doc: &Document) -> std::result::Result<Vec<Match>, crate::doc::Error> {
+        let mut matches = Vec::new();
+        for m in doc.find(&self.query)? {
+            if let Some(filter) = &self.filter {
+                if !filter.is_match(doc, &m)? {
+                    continue;
+                }
+            }
+            matches.push(m);
+        }
+        Ok(matches)
+    }
+}
+
+/// A set of rules, usually loaded from one or more TOML files with `[[rule]]`
+/// tables.
+#[derive(Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        Self::parse(path, &source)
+    }
+
+    pub fn parse<P: AsRef<Path>>(path: P, source: &str) -> Result<Self> {
+        let file: RulesFile =
+            toml::from_str(source).map_err(|e| Error::Parse(path.as_ref().to_owned(), e))?;
+        let mut rules = Self::default();
+        for config in file.rules {
+            rules.push(Rule::new(config)?)?;
+        }
+        Ok(rules)
+    }
+
+    pub fn push(&mut self, rule: Rule) -> Result<()> {
+        if self.rules.iter().any(|r| r.id == rule.id) {
+            return Err(Error::Duplicate(rule.id));
+        }
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    pub fn extend(&mut self, other: Self) -> Result<()> {
+        for rule in other.rules {
+            self.push(rule)?;
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// All languages for which at least one rule exists.
+    pub fn languages(&self) -> BTreeSet<Language> {
+        self.rules.iter().map(|r| r.language).collect()
+    }
+
+    pub fn for_language(&self, language: Language) -> impl Iterator<Item = &Rule> {
+        self.rules.iter().filter(move |r| r.language == language)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        [[rule]]
+        id = "no-print"
+        language = "python"
+        query = '((call function: (identifier) @f) @call (#eq? @f "print"))'
+
+        [[rule]]
+        id = "no-eval"
+        language = "python"
+        query = '((call function: (identifier) @f) @call (#eq? @f "eval"))'
+        message = "eval is unsafe"
+        severity = "error"
+        script = 'document.report(found.captures[0].range, "eval of " + found.captures[0].text, "error")'
+    "#;
+
+    fn check(rules: &Rules, content: &str) -> Vec<Report> {
+        let doc = crate::testing::doc(Language::Python, content);
+        let rules = rules.for_language(Language::Python);
+        rules.flat_map(|r| r.check(&doc).unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rules = Rules::parse("ssr.toml", RULES).unwrap();
+        let ids = rules.iter().map(Rule::id).collect::<Vec<_>>();
+        assert_eq!(ids, ["no-print", "no-eval"]);
+        let print = rules.iter().next().unwrap();
+        assert_eq!(print.message(), "no-print");
+        assert_eq!(print.severity(), Severity::Warning);
+        assert_eq!(rules.languages(), BTreeSet::from([Language::Python]));
+        assert_eq!(rules.for_language(Language::Rust).count(), 0);
+
+        let invalid = |source: &str| Rules::parse("ssr.toml", source).err().unwrap();
+        let rule = |field: &str| {
+            format!("[[rule]]\nid = \"a\"\nlanguage = \"python\"\nquery = \"(call)\"\n{field}\n")
+        };
+        assert!(matches!(
+            invalid(&rule("severity = \"fatal\"")),
+            Error::Severity(..)
+        ));
+        assert!(matches!(invalid(&rule("extra = 1")), Error::Parse(..)));
+        assert!(matches!(
+            invalid(&rule("").replace("python", "cobol")),
+            Error::Language(..)
+        ));
+        assert!(matches!(
+            invalid(&rule("").replace("(call)", "(call")),
+            Error::Query(..)
+        ));
+        assert!(matches!(
+            invalid(&format!("{}{}", rule(""), rule(""))),
+            Error::Duplicate(_)
+        ));
+
+        let mut rules = Rules::parse("a.toml", RULES).unwrap();
+        let other = Rules::parse("b.toml", RULES).unwrap();
+        assert!(matches!(rules.extend(other), Err(Error::Duplicate(_))));
+    }
+
+    #[test]
+    fn reports_with_severity() {
+        let rules = Rules::parse("ssr.toml", RULES).unwrap();
+        let reports = check(&rules, "print(1)\neval(x)\n");
+        let found = reports
+            .iter()
+            .map(|r| (r.rule().unwrap(), r.message(), r.severity()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("no-print", "no-print", Severity::Warning),
+                ("no-eval", "eval of eval(x)", Severity::Error),
+            ]
+        );
+        assert!(check(&rules, "x = 1\n").is_empty());
+    }
+
+    #[test]
+    fn stable_fingerprints() {
+        let rules = Rules::parse("ssr.toml", RULES).unwrap();
+        let fingerprints = |content| {
+            let reports = check(&rules, content);
+            reports
+                .iter()
+                .map(|r| r.fingerprint().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let before = fingerprints("print(1)\neval(x)\n");
+        // Moved and indented code keeps its fingerprints.
+        assert_eq!(
+            before,
+            fingerprints("import os\n\nif True:\n    print(1)\n    eval(x)\n")
+        );
+        // Reports of a script are told apart by the reported code.
+        let other = fingerprints("print(2)\neval(y)\n");
+        assert_ne!(before[0], other[0]);
+        assert_ne!(before[1], other[1]);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::{
+    doc::{DocumentEdits, Error},
+    Change, Document, Match, Report, Workspace,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Create a Rhai engine with all types known which are exposed to scripts.
+pub(crate) fn engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.build_type::<DocumentEdits>();
+    engine.build_type::<crate::Match>();
+    engine.build_type::<crate::Capture>();
+    engine.build_type::<Workspace>();
+    engine.register_fn("range", range);
+    engine
+}
+
+/// A range from one based lines and columns, counted in bytes like the
+/// locations in reports, which ends before `end_column`. The bytes are looked
+/// up in the document the range is used for.
+fn range(
+    start_line: i64,
+    start_column: i64,
+    end_line: i64,
+    end_column: i64,
+) -> std::result::Result<tree_sitter::Range, Box<rhai::EvalAltResult>> {
+    let point = |line: i64, column: i64| {
+        let row = usize::try_from(line).ok().filter(|&l| l > 0)? - 1;
+        let column = usize::try_from(column).ok().filter(|&c| c > 0)? - 1;
+        Some(tree_sitter::Point { row, column })
+    };
+    let (start_point, end_point) = point(start_line, start_column)
+        .zip(point(end_line, end_column))
+        .ok_or("lines and columns of a range start at 1")?;
+    Ok(tree_sitter::Range {
+        start_byte: crate::doc::UNLOCATED,
+        end_byte: crate::doc::UNLOCATED,
+        start_point,
+        end_point,
+    })
+}
+
+/// A Rhai expression which decides if a match is kept.
+///
+/// The expression is evaluated with the same `found` object in scope as the
+/// replacement script of [`Document::edit`] and must return a boolean.
+pub struct Filter {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+}
+
+impl Filter {
+    pub fn new(source: &str) -> Result<Self> {
+        let engine = engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| Error::Compile(e.to_string()))?;
+        Ok(Self { engine, ast })
+    }
+
+    pub fn is_match(&self, doc: &Document, m: &Match) -> Result<bool> {
+        let mut scope = rhai::Scope::new();
+        scope.push("found", m.clone());
+        self.engine
+            .eval_ast_with_scope::<bool>(&mut scope, &self.ast)
+            .map_err(|e| Error::Script(doc.path().to_owned(), e.to_string()))
+    }
+}
+
+/// A compiled Rhai script which is evaluated for every match.
+///
+/// The script can modify the document via `document.edit(range, text)` and
+/// report findings via `document.report(range, message, severity)`. With a
+/// [`Workspace`] other files can be changed via the `workspace` object.
+///
+/// A script which defines `fn collect(document, found)` and
+/// `fn rewrite(document, found, workspace)` works in two phases: `collect` is
+/// called for every match of all documents first with the [`Facts`] map as
+/// `this`, see [`Script::collect`], then `rewrite` is called for every match
+/// instead of evaluating the script, with a copy of the facts per document as
+/// `this`.
+pub struct Script {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+    workspace: Option<Workspace>,
+    facts: Option<Facts>,
+}
+
+/// A Rhai map filled by [`Script::collect`] for all documents, which is then
+/// available to the `rewrite` function of the script.
+#[derive(Debug, Clone, Default)]
+pub struct Facts(rhai::Map);
+
+impl Script {
+    pub fn new(source: &str) -> Result<Self> {
+        let engine = engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| Error::Compile(e.to_string()))?;
+        let script = Self {
+            engine,
+            ast,
+            workspace: None,
+            facts: None,
+        };
+        if script.has_collect() && !script.has_fn("rewrite", 3) {
+            let message = "a script with `fn collect(document, found)` also needs \
+                           `fn rewrite(document, found, workspace)`";
+            return Err(Error::Compile(message.to_owned()));
+        }
+        Ok(script)
+    }
+
+    /// Provide a copy of `facts` to every document, changes to it are seen by
+    /// the later matches of the same document only.
+    pub fn with_facts(mut self, facts: Facts) -> Self {
+        self.facts = Some(facts);
+        self
+    }
+
+    /// Collect the changes to other files in `workspace`.
+    pub fn with_workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = Some(workspace);
+        self
+    }
+
+    /// Check if the script defines a function `name` with `params` parameters.
+    fn has_fn(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+
+    /// Check if the script has a `collect` phase, which has to run for all
+    /// documents before any of them is rewritten.
+    pub fn has_collect(&self) -> bool {
+        self.has_fn("collect", 2)
+    }
+
+    pub fn run(
+        &self,
+        doc: &Document,
+        matches: impl IntoIterator<Item = Match>,
+    ) -> Result<ScriptOutput> {
+        let edits = DocumentEdits::new(doc.path().to_owned());
+        let mut scope = rhai::Scope::new();
+        scope.push("document", edits.clone());
+        if let Some(workspace) = &self.workspace {
+            scope.push("workspace", workspace.clone());
+        }
+
+        let rewrite = self.has_fn("rewrite", 3);
+        let workspace = self
+            .workspace
+            .clone()
+            .map_or(rhai::Dynamic::UNIT, rhai::Dynamic::from);
+        let mut facts = rhai::Dynamic::from_map(self.facts.clone().unwrap_or_default().0);
+        for m in matches {
+            let result = if rewrite {
+                let options = rhai::CallFnOptions::new()
+                    .eval_ast(false)
+                    .bind_this_ptr(&mut facts);
+                let args = (edits.clone(), m, workspace.clone());
+                self.engine
+                    .call_fn_with_options(options, &mut scope, &self.ast, "rewrite", args)
+            } else {
+                scope.set_value("found", m);
+                self.engine.eval_ast_with_scope(&mut scope, &self.ast)
+            };
+            let _result: rhai::Dynamic =
+                result.map_err(|e| Error::Script(doc.path().to_owned(), e.to_string()))?;
+        }
+        let invalid = || Error::Script(doc.path().to_owned(), "invalid range".to_owned());
+        let mut changes = edits
+            .changes()
+            .into_iter()
+            .map(|c| c.locate(doc))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let reports = edits
+            .reports()
+            .into_iter()
+            .map(|r| r.locate(doc))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let imports = edits.imports();
+        if !imports.is_empty() {
+            let imports = crate::import::changes(doc, &imports, &changes)
+                .map_err(|e| Error::Script(doc.path().to_owned(), e))?;
+            changes.extend(imports);
+        }
+        Ok(ScriptOutput { changes, reports })
+    }
+
+    /// Call the `collect` function of the script for all matches in `doc` to
+    /// record what it learns in the `facts` map, which it gets as `this`. The
+    /// document can not be edited.
+    pub fn collect(
+        &self,
+        doc: &Document,
+        matches: impl IntoIterator<Item = Match>,
+        facts: &mut Facts,
+    ) -> Result<()> {
+        let edits = DocumentEdits::new(doc.path().to_owned());
+        let mut scope = rhai::Scope::new();
+        let mut map = rhai::Dynamic::from_map(std::mem::take(&mut facts.0));
+
+        let mut result = Ok(());
+        for m in matches {
+            let options = rhai::CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut map);
+            result = self
+                .engine
+                .call_fn_with_options::<rhai::Dynamic>(
+                    options,
+                    &mut scope,
+                    &self.ast,
+                    "collect",
+                    (edits.clone(), m),
+                )
+                .map(|_| ())
+                .map_err(|e| Error::Script(doc.path().to_owned(), e.to_string()));
+            if result.is_err() {
+                break;
+            }
+        }
+        // Keep what was collected so far, even if the script failed.
+        match map.try_cast::<rhai::Map>() {
+            Some(map) => facts.0 = map,
+            None => {
+                let message = "`this` of collect has to stay a map";
+                return Err(Error::Script(doc.path().to_owned(), message.to_owned()));
+            }
+        }
+        result?;
+        if !edits.changes().is_empty() || !edits.imports().is_empty() {
+            let message = "documents can not be edited while collecting facts";
+            return Err(Error::Script(doc.path().to_owned(), message.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Changes and reports collected while running a [`Script`].
+#[derive(Debug, Clone, Default)]
+pub struct ScriptOutput {
+    changes: Vec<Change>,
+    reports: Vec<Report>,
+}
+
+impl ScriptOutput {
+    pub fn changes(&self) -> &[Change] {
+        self.changes.as_slice()
+    }
+    pub fn reports(&self) -> &[Report] {
+        self.reports.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Language, Query};
+
+    const SCRIPT: &str = r#"
+        fn collect(document, found) {
+            let name = found.captures[0].text;
+            this[name] = (this[name] ?? 0) + 1;
+        }
+        fn rewrite(document, found, workspace) {
+            let c = found.captures[0];
+            if this[c.text] == 1 { document.edit(c.range, "once"); }
+            this[c.text] = 0;
+        }
+    "#;
+
+    #[test]
+    fn collect_then_rewrite() {
+        let doc = crate::testing::doc(Language::Rust, "a(b, b);");
+        let query = Query::new(Language::Rust, "(identifier) @id").unwrap();
+        let script = Script::new(SCRIPT).unwrap();
+        assert!(script.has_collect());
+
+        let mut facts = Facts::default();
+        script
+            .collect(&doc, doc.find(&query).unwrap(), &mut facts)
+            .unwrap();
+        assert_eq!(facts.0.len(), 2);
+        assert_eq!(facts.0["b"].as_int(), Ok(2));
+
+        let script = script.with_facts(facts.clone());
+        let output = script.run(&doc, doc.find(&query).unwrap()).unwrap();
+        let changes = output.changes();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].replacement(), "once");
+        assert_eq!(changes[0].range().start_byte, 0);
+        // Changes to the facts while rewriting are not kept.
+        assert_eq!(script.facts.unwrap().0["a"].as_int(), Ok(1));
+    }
+
+    #[test]
+    fn rewrite_state_per_document() {
+        let script = r#"
+            fn collect(document, found) {}
+            fn rewrite(document, found, workspace) {
+                if this.done != true { document.edit(found.captures[0].range, "first"); }
+                this.done = true;
+            }
+        "#;
+        let script = Script::new(script).unwrap().with_facts(Facts::default());
+        let query = Query::new(Language::Rust, "(identifier) @id").unwrap();
+        for path in ["a.rs", "b.rs"] {
+            let doc =
+                Document::with_content(path.into(), Language::Rust, "a(b);".to_owned()).unwrap();
+            let output = script.run(&doc, doc.find(&query).unwrap()).unwrap();
+            assert_eq!(output.changes().len(), 1, "{path}");
+        }
+    }
+
+    #[test]
+    fn report_by_lines_and_columns() {
+        let doc = crate::testing::doc(Language::Rust, "fn a() {}\nfn b() {}\n");
+        let query = Query::new(Language::Rust, "(function_item) @f").unwrap();
+        let script = Script::new(r#"document.report(range(2, 4, 2, 5), "b", "warning")"#).unwrap();
+        let output = script.run(&doc, doc.find(&query).unwrap().take(1)).unwrap();
+        let range = output.reports()[0].range();
+        assert_eq!(range.start_byte..range.end_byte, 13..14);
+        assert_eq!(&doc.content()[range.start_byte..range.end_byte], "b");
+
+        let script = Script::new(r#"document.report(range(5, 1, 5, 2), "x", "warning")"#).unwrap();
+        let result = script.run(&doc, doc.find(&query).unwrap());
+        assert!(matches!(result, Err(Error::Script(..))));
+    }
+
+    #[test]
+    fn collect_needs_rewrite() {
+        let script = "fn collect(document, found) {}";
+        assert!(matches!(Script::new(script), Err(Error::Compile(_))));
+        assert!(!Script::new("document.edit(found.range, \"\")")
+            .unwrap()
+            .has_collect());
+    }
+}
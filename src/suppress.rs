@@ -0,0 +1,280 @@
+use crate::{baseline::fingerprint, Document, Report, Severity};
+
+/// Rule id used for findings about suppressions which did not suppress
+/// anything.
+pub const UNUSED_SUPPRESSION: &str = "unused-suppression";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// Suppress findings which start on one of the rows, inclusive.
+    Rows(usize, usize),
+    File,
+}
+
+#[derive(Debug, Clone)]
+struct Suppression {
+    range: tree_sitter::Range,
+    text: String,
+    scope: Scope,
+    /// Suppressed rules, all rules when empty.
+    rules: Vec<String>,
+    /// Which of the `rules` (or the single wildcard) suppressed a finding.
+    used: Vec<bool>,
+}
+
+/// Suppression comments of a document.
+///
+/// A comment `ssr-ignore` suppresses findings on its own line or, when the
+/// comment is on a line by itself, in the following statement or item. Specific
+/// rules are given after a colon, e.g. `// ssr-ignore: no-dbg, no-todo`, a bare
+/// `ssr-ignore` suppresses all rules. Text after `--` is ignored and can be
+/// used to document the reason, other text without a colon makes the comment
+/// no suppression. With `ssr-ignore-file` the findings of the whole file are
+/// suppressed.
+#[derive(Debug, Default)]
+pub struct Suppressions {
+    items: Vec<Suppression>,
+}
+
+impl Suppressions {
+    pub fn new(doc: &Document) -> Self {
+        let kinds = doc.language().comment_kinds();
+        let mut items = Vec::new();
+        let mut cursor = doc.tree().walk();
+        loop {
+            let node = cursor.node();
+            let is_comment = kinds.contains(&node.kind());
+            if is_comment {
+                if let Some(s) = Suppression::new(doc, node, kinds) {
+                    items.push(s);
+                }
+            }
+            if !is_comment && cursor.goto_first_child() {
+                continue;
+            }
+            if cursor.goto_next_sibling() {
+                continue;
+            }
+            loop {
+                if !cursor.goto_parent() {
+                    return Self { items };
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Check if `report` is suppressed and record the suppression as used.
+    pub fn suppresses(&mut self, report: &Report) -> bool {
+        let row = report.range().start_point.row;
+        let rule = report.rule().unwrap_or_default();
+        let mut suppressed = false;
+        for s in self.items.iter_mut() {
+            let in_scope = match s.scope {
+                Scope::Rows(start, end) => (start..=end).contains(&row),
+                Scope::File => true,
+            };
+            if !in_scope {
+                continue;
+            }
+            if s.rules.is_empty() {
+                s.used[0] = true;
+                suppressed = true;
+            } else if let Some(k) = s.rules.iter().position(|r| r == rule) {
+                s.used[k] = true;
+                suppressed = true;
+            }
+        }
+        suppressed
+    }
+
+    /// Findings for suppressions which did not suppress anything so far.
+    pub fn unused(&self, doc: &Document) -> Vec<Report> {
+        let mut reports = Vec::new();
+        for s in self.items.iter() {
+            let names = if s.rules.is_empty() {
+                vec![None]
+            } else {
+                s.rules.iter().map(|r| Some(r.as_str())).collect()
+            };
+            for (name, used) in names.into_iter().zip(s.used.iter()) {
+                if *used {
+                    continue;
+                }
+                let message = match name {
+                    Some(name) => format!("unused suppression for `{name}`"),
+                    None => "unused suppression".to_owned(),
+                };
+                let fingerprint =
+                    fingerprint([UNUSED_SUPPRESSION, s.text.as_str(), name.unwrap_or("")]);
+                let report =
+                    Report::new(doc.path().to_owned(), s.range, message, Severity::Warning)
+                        .with_rule(UNUSED_SUPPRESSION)
+                        .with_fingerprint(fingerprint);
+                reports.push(report);
+            }
+        }
+        reports
+    }
+}
+
+impl Suppression {
+    fn new(doc: &Document, node: tree_sitter::Node, kinds: &[&str]) -> Option<Self> {
+        let text = node.utf8_text(doc.content().as_bytes()).ok()?;
+        let body = text
+            .trim_start_matches(['/', '*', '!', '#'])
+            .trim_end_matches("*/")
+            .trim();
+        let (is_file, rest) = if let Some(rest) = body.strip_prefix("ssr-ignore-file") {
+            (true, rest)
+        } else if let Some(rest) = body.strip_prefix("ssr-ignore") {
+            (false, rest)
+        } else {
+            return None;
+        };
+        let rules = if let Some(rest) = rest.strip_prefix(':') {
+            rest.split("--")
+                .next()
+                .unwrap_or_default()
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|r| !r.is_empty())
+                .map(|r| r.to_owned())
+                .collect::<Vec<_>>()
+        } else if rest.trim().is_empty() || rest.trim_start().starts_with("--") {
+            Vec::new()
+        } else {
+            // A word which starts with `ssr-ignore`, or text which might be
+            // meant as rules, do not silently suppress everything.
+            return None;
+        };
+
+        let scope = if is_file {
+            Scope::File
+        } else {
+            let start = node.start_position();
+            let line_start = node.start_byte() - start.column;
+            let standalone = doc.content()[line_start..node.start_byte()]
+                .chars()
+                .all(char::is_whitespace);
+            if standalone {
+                // Applies to the code which follows the comment.
+                let mut next = node.next_sibling();
+                while let Some(n) = next.filter(|n| kinds.contains(&n.kind())) {
+                    next = n.next_sibling();
+                }
+                // A Python comment before the first statement of a body is
+                // followed by the whole block.
+                while let Some(block) = next.filter(|n| n.kind() == "block") {
+                    let mut cursor = block.walk();
+                    let first = block
+                        .named_children(&mut cursor)
+                        .find(|n| !kinds.contains(&n.kind()));
+                    next = first;
+                }
+                match next {
+                    Some(n) => Scope::Rows(n.start_position().row, n.end_position().row),
+                    None => Scope::Rows(node.end_position().row + 1, node.end_position().row + 1),
+                }
+            } else {
+                Scope::Rows(start.row, start.row)
+            }
+        };
+
+        Some(Self {
+            range: node.range(),
+            text: text.to_owned(),
+            scope,
+            used: vec![false; rules.len().max(1)],
+            rules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::doc, Language};
+
+    fn report(row: usize, rule: &str) -> Report {
+        let point = tree_sitter::Point { row, column: 0 };
+        let range = tree_sitter::Range {
+            start_byte: 0,
+            end_byte: 0,
+            start_point: point,
+            end_point: point,
+        };
+        Report::new("test".into(), range, String::new(), Severity::Warning).with_rule(rule)
+    }
+
+    fn rules(s: &Suppressions) -> Vec<Vec<&str>> {
+        let rules = s.items.iter().map(|i| i.rules.iter().map(String::as_str));
+        rules.map(Iterator::collect).collect()
+    }
+
+    #[test]
+    fn parse_rules_and_reason() {
+        let doc = doc(
+            Language::Rust,
+            "// ssr-ignore: a, b c -- reason: d\n\
+             /* ssr-ignore */\n\
+             // ssr-ignore -- all rules\n\
+             // ssr-ignore-file: e\n\
+             // ssr-ignore some reason\n\
+             // ssr-ignored\n\
+             fn f() {}\n",
+        );
+        let s = Suppressions::new(&doc);
+        assert_eq!(
+            rules(&s),
+            vec![vec!["a", "b", "c"], vec![], vec![], vec!["e"]]
+        );
+        assert_eq!(s.items[3].scope, Scope::File);
+    }
+
+    #[test]
+    fn trailing_comment_covers_its_line() {
+        let doc = doc(Language::Python, "x = 1\ny = 2  # ssr-ignore: a\nz = 3\n");
+        let mut s = Suppressions::new(&doc);
+        assert!(!s.suppresses(&report(0, "a")));
+        assert!(s.suppresses(&report(1, "a")));
+        assert!(!s.suppresses(&report(1, "b")));
+        assert!(!s.suppresses(&report(2, "a")));
+    }
+
+    #[test]
+    fn standalone_comment_covers_next_statement() {
+        let doc = doc(
+            Language::Rust,
+            "fn f() {\n    // ssr-ignore\n    // more\n    call(\n        1,\n    );\n    other();\n}\n",
+        );
+        let mut s = Suppressions::new(&doc);
+        assert!(s.suppresses(&report(3, "a")));
+        assert!(s.suppresses(&report(4, "a")));
+        assert!(!s.suppresses(&report(6, "a")));
+    }
+
+    #[test]
+    fn standalone_comment_before_python_body() {
+        let doc = doc(
+            Language::Python,
+            "def f():\n    # ssr-ignore\n    print(1)\n    print(2)\n    print(3)\n",
+        );
+        let mut s = Suppressions::new(&doc);
+        assert!(s.suppresses(&report(2, "a")));
+        assert!(!s.suppresses(&report(3, "a")));
+        assert!(!s.suppresses(&report(4, "a")));
+    }
+
+    #[test]
+    fn file_scope_and_unused() {
+        let doc = doc(Language::Python, "# ssr-ignore-file: a, b\nx = 1\n");
+        let mut s = Suppressions::new(&doc);
+        assert!(s.suppresses(&report(1, "a")));
+        let unused = s.unused(&doc);
+        assert_eq!(unused.len(), 1);
+        assert_eq!(unused[0].message(), "unused suppression for `b`");
+        assert_eq!(unused[0].rule(), Some(UNUSED_SUPPRESSION));
+    }
+}
@@ -0,0 +1,37 @@
+//
+// Helpers shared by the unit tests.
+//
+use crate::{Document, Language};
+use std::path::{Path, PathBuf};
+
+/// A document with `content`, named `a.rs`, `a.py` or `BUILD` after `lang`.
+pub(crate) fn doc(lang: Language, content: &str) -> Document {
+    let path = match lang {
+        Language::Rust => "a.rs",
+        Language::Python => "a.py",
+        Language::Bazel => "BUILD",
+    };
+    Document::with_content(path.into(), lang, content.to_owned()).unwrap()
+}
+
+/// An empty directory for the files of a test, removed when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` keeps the directories of tests running in parallel apart.
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("ssr-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
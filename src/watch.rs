@@ -0,0 +1,88 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use notify::{EventKind, RecursiveMode};
+
+/// Time to wait for further events before reporting a change, editors often
+/// write a file in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches directories recursively for changed files.
+///
+/// File system notifications are used when available, otherwise the
+/// directories are polled.
+pub struct Watcher {
+    _watcher: Box<dyn notify::Watcher + Send>,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl Watcher {
+    pub fn new(paths: &[PathBuf]) -> notify::Result<Self> {
+        let (tx, events) = channel();
+        let watcher = match notify::recommended_watcher(tx.clone()) {
+            Ok(mut watcher) => match watch_all(&mut watcher, paths) {
+                Ok(()) => Some(Box::new(watcher) as Box<dyn notify::Watcher + Send>),
+                Err(_) => None,
+            },
+            Err(_) => None,
+        };
+        let watcher = match watcher {
+            Some(watcher) => watcher,
+            None => {
+                let config = notify::Config::default().with_poll_interval(POLL_INTERVAL);
+                let mut watcher = notify::PollWatcher::new(tx, config)?;
+                watch_all(&mut watcher, paths)?;
+                Box::new(watcher)
+            }
+        };
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Block until files were created, modified or removed and return their
+    /// paths.
+    pub fn wait(&self) -> notify::Result<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        let mut timeout = None;
+        loop {
+            let event = match timeout {
+                None => self
+                    .events
+                    .recv()
+                    .map_err(|e| notify::Error::generic(&e.to_string()))?,
+                Some(timeout) => match self.events.recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => return Ok(changed),
+                    Err(e @ RecvTimeoutError::Disconnected) => {
+                        return Err(notify::Error::generic(&e.to_string()))
+                    }
+                },
+            };
+            let event = event?;
+            if matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                changed.extend(event.paths);
+            }
+            if !changed.is_empty() {
+                timeout = Some(DEBOUNCE);
+            }
+        }
+    }
+}
+
+fn watch_all(watcher: &mut impl notify::Watcher, paths: &[PathBuf]) -> notify::Result<()> {
+    for p in paths {
+        watcher.watch(p, RecursiveMode::Recursive)?;
+    }
+    Ok(())
+}
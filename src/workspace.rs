@@ -0,0 +1,407 @@
+//
+// Changes to several files collected while running scripts.
+//
+use crate::{Change, Document, Language, OpenOptions};
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Document(#[from] crate::DocumentError),
+    #[error("{0} already exists")]
+    Exists(PathBuf),
+    #[error("Can not rename {0}, it is created by the script")]
+    Rename(PathBuf),
+    #[error("Invalid range for {0}")]
+    Range(PathBuf),
+    #[error("Can not rewrite {0}, it is not UTF-8 and was only decoded for reading")]
+    Decoded(PathBuf),
+}
+
+/// Edits, new files and renames requested by scripts via the `workspace`
+/// object, collected across all documents of a run.
+///
+/// Paths are relative to the working directory like the paths of the walked
+/// documents.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    language: Language,
+    options: OpenOptions,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    edits: BTreeMap<PathBuf, Vec<Edit>>,
+    created: BTreeMap<PathBuf, String>,
+    renamed: BTreeMap<PathBuf, PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Edit {
+    Change(Change),
+    /// Insert text before a one based line.
+    Insert(usize, String),
+}
+
+/// A file which is one of the documents of a run or only touched through the
+/// workspace.
+struct Entry {
+    path: PathBuf,
+    old: Option<Document>,
+    created: Option<Document>,
+    changes: Vec<Change>,
+}
+
+impl Workspace {
+    /// Files are opened with `options` and parsed as `language` unless their
+    /// name tells otherwise.
+    pub fn new(language: Language, options: OpenOptions) -> Self {
+        Self {
+            language,
+            options,
+            state: Default::default(),
+        }
+    }
+
+    fn language(&self, path: &Path) -> Language {
+        Language::from_path(path).unwrap_or(self.language)
+    }
+
+    fn edit(&self, path: &str, edit: Edit) {
+        let mut state = self.state.lock().unwrap();
+        let edits = state.edits.entry(path.into()).or_default();
+        // Scripts run for every match, so the same edit is requested repeatedly.
+        if !edits.contains(&edit) {
+            edits.push(edit);
+        }
+    }
+
+    /// Combine the `edited` documents and their changes with the changes made
+    /// through the workspace, returning all files which differ.
+    pub fn changes(
+        &self,
+        edited: impl IntoIterator<Item = (Document, Vec<Change>)>,
+    ) -> Result<Vec<FileChange>> {
+        let mut files = BTreeMap::new();
+        for (doc, changes) in edited {
+            files.insert(
+                normalize(doc.path()),
+                Entry {
+                    path: doc.path().to_owned(),
+                    old: Some(doc),
+                    created: None,
+                    changes,
+                },
+            );
+        }
+
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        for (path, content) in state.created {
+            if path.exists() || files.contains_key(&normalize(&path)) {
+                return Err(Error::Exists(path));
+            }
+            let doc = Document::with_content(path.clone(), self.language(&path), content)?;
+            files.insert(
+                normalize(&path),
+                Entry {
+                    path,
+                    old: None,
+                    created: Some(doc),
+                    changes: Vec::new(),
+                },
+            );
+        }
+        for (path, edits) in state.edits {
+            let entry = self.entry(&mut files, &path)?;
+            let doc = entry.base();
+            let mut changes = Vec::new();
+            for edit in edits {
+                let change = match edit {
+                    Edit::Change(c) => c.locate(doc).ok_or_else(|| Error::Range(path.clone()))?,
+                    Edit::Insert(line, text) => {
+                        let start = line_start(doc.content(), line);
+                        Change::new(doc.range(start..start), text)
+                    }
+                };
+                let r = change.range();
+                let valid = r.start_byte <= r.end_byte
+                    && doc.content().get(r.start_byte..r.end_byte).is_some();
+                if !valid {
+                    return Err(Error::Range(path));
+                }
+                changes.push(change);
+            }
+            entry.changes.extend(changes);
+        }
+        for (from, to) in state.renamed {
+            let key = normalize(&to);
+            if to.exists() || files.contains_key(&key) {
+                return Err(Error::Exists(to));
+            }
+            let entry = self.entry(&mut files, &from)?;
+            if entry.old.is_none() {
+                return Err(Error::Rename(from));
+            }
+            entry.path = to;
+        }
+
+        let mut changes = Vec::new();
+        for entry in files.into_values() {
+            // Writing UTF-8 would silently change the encoding of the file.
+            let decoded = entry.old.as_ref().is_some_and(Document::is_decoded);
+            if decoded && !entry.changes.is_empty() {
+                return Err(Error::Decoded(entry.path));
+            }
+            let new = entry.base().apply_edits(entry.changes.iter().cloned())?;
+            let change = FileChange {
+                old: entry.old,
+                new: new.with_path(entry.path),
+            };
+            if change.is_changed() {
+                changes.push(change);
+            }
+        }
+        Ok(changes)
+    }
+
+    fn entry<'a>(
+        &self,
+        files: &'a mut BTreeMap<PathBuf, Entry>,
+        path: &Path,
+    ) -> Result<&'a mut Entry> {
+        let key = normalize(path);
+        if !files.contains_key(&key) {
+            let doc = Document::open_with(path, self.language(path), &self.options)?;
+            let entry = Entry {
+                path: path.to_owned(),
+                old: Some(doc),
+                created: None,
+                changes: Vec::new(),
+            };
+            files.insert(key.clone(), entry);
+        }
+        Ok(files.get_mut(&key).expect("entry was inserted"))
+    }
+}
+
+impl Entry {
+    /// The document the changes apply to.
+    fn base(&self) -> &Document {
+        self.created
+            .as_ref()
+            .or(self.old.as_ref())
+            .expect("entry has a document")
+    }
+}
+
+impl rhai::CustomType for Workspace {
+    fn build(mut builder: rhai::TypeBuilder<Self>) {
+        builder
+            .with_name("Workspace")
+            .with_fn("edit", |this: &mut Self, path: &str, range, text| {
+                this.edit(path, Edit::Change(Change::new(range, text)));
+            })
+            .with_fn("insert", |this: &mut Self, path: &str, line: i64, text| {
+                let line = usize::try_from(line).unwrap_or_default();
+                this.edit(path, Edit::Insert(line, text));
+            })
+            .with_fn("create", |this: &mut Self, path: &str, text: String| {
+                let mut state = this.state.lock().unwrap();
+                state.created.insert(path.into(), text);
+            })
+            .with_fn("rename", |this: &mut Self, from: &str, to: &str| {
+                let mut state = this.state.lock().unwrap();
+                state.renamed.insert(from.into(), to.into());
+            });
+    }
+}
+
+/// A file which was edited, created or renamed.
+pub struct FileChange {
+    old: Option<Document>,
+    new: Document,
+}
+
+impl FileChange {
+    /// The original document, `None` for new files.
+    pub fn original(&self) -> Option<&Document> {
+        self.old.as_ref()
+    }
+
+    /// The changed document, at the new path for renamed files.
+    pub fn document(&self) -> &Document {
+        &self.new
+    }
+
+    /// Replace the new version of the document, e.g. after formatting it.
+    pub fn with_new(self, new: Document) -> Self {
+        Self { new, ..self }
+    }
+
+    pub fn is_created(&self) -> bool {
+        self.old.is_none()
+    }
+
+    pub fn is_renamed(&self) -> bool {
+        self.old
+            .as_ref()
+            .is_some_and(|old| old.path() != self.new.path())
+    }
+
+    pub fn is_changed(&self) -> bool {
+        match &self.old {
+            Some(old) => self.is_renamed() || old.diff(&self.new).is_changed(),
+            None => true,
+        }
+    }
+
+    /// Zero based rows of the new document which were inserted or replaced.
+    pub fn changed_lines(&self) -> Vec<Range<usize>> {
+        match &self.old {
+            Some(old) => old.diff(&self.new).changed_lines(),
+            None => std::iter::once(0..self.new.content().lines().count()).collect(),
+        }
+    }
+}
+
+/// Git style patch, with headers for new and renamed files.
+impl std::fmt::Display for FileChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let new = self.new.path().display();
+        match &self.old {
+            None => {
+                writeln!(f, "diff --git a/{new} b/{new}")?;
+                writeln!(f, "new file mode 100644")?;
+                let b = format!("b/{new}");
+                let content = self.new.file_content();
+                similar::TextDiff::from_lines("", content.as_ref())
+                    .unified_diff()
+                    .header("/dev/null", &b)
+                    .fmt(f)
+            }
+            Some(old) if self.is_renamed() => {
+                let old_path = old.path().display();
+                writeln!(f, "diff --git a/{old_path} b/{new}")?;
+                writeln!(f, "rename from {old_path}")?;
+                writeln!(f, "rename to {new}")?;
+                let patch = old.diff(&self.new);
+                if patch.is_changed() {
+                    write!(f, "{patch}")?;
+                }
+                Ok(())
+            }
+            Some(old) => write!(f, "{}", old.diff(&self.new)),
+        }
+    }
+}
+
+/// Byte offset of the start of the one based `line`, or the end of `content`
+/// for lines past the end.
+fn line_start(content: &str, line: usize) -> usize {
+    if line <= 1 {
+        return 0;
+    }
+    content
+        .match_indices('\n')
+        .nth(line - 2)
+        .map_or(content.len(), |(i, _)| i + 1)
+}
+
+/// Compare paths without `.` components, like `./src/lib.rs` and
+/// `src/lib.rs`.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Query, Script};
+
+    /// Run `script` for every `(identifier)` in `content` and return the
+    /// changed files.
+    fn run(content: &str, script: &str) -> Result<Vec<FileChange>> {
+        let workspace = Workspace::new(Language::Rust, OpenOptions::default());
+        let doc = crate::testing::doc(Language::Rust, content);
+        let query = Query::new(Language::Rust, "(identifier) @id").unwrap();
+        let script = Script::new(script)
+            .unwrap()
+            .with_workspace(workspace.clone());
+        let output = script.run(&doc, doc.find(&query).unwrap()).unwrap();
+        workspace.changes([(doc, output.changes().to_vec())])
+    }
+
+    #[test]
+    fn edit_created_file_by_lines_and_columns() {
+        let script = r#"
+            workspace.create("b.rs", "fn b() {\n    one();\n}\n");
+            workspace.edit("b.rs", range(2, 5, 2, 8), found.captures[0].text);
+        "#;
+        let changes = run("fn two() {}\n", script).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_created());
+        assert_eq!(changes[0].document().content(), "fn b() {\n    two();\n}\n");
+    }
+
+    #[test]
+    fn workspace_edit_of_rewritten_document() {
+        // Both edits are located in the original content and combined into one
+        // change of the file.
+        let script = r#"
+            let id = found.captures[0];
+            if id.text == "m" { document.edit(id.range, "main"); }
+            workspace.edit("a.rs", range(2, 5, 2, 6), "renamed");
+        "#;
+        let content = "fn m() {\n    f();\n}\n";
+        let changes = run(content, script).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].document().content(),
+            "fn main() {\n    renamed();\n}\n"
+        );
+    }
+
+    #[test]
+    fn decoded_files_are_not_rewritten() {
+        let dir = crate::testing::TempDir::new("decoded");
+        let path = dir.path().join("a.rs");
+        std::fs::write(&path, b"fn caf\xe9() {}\n").unwrap();
+        let options = OpenOptions {
+            decode: true,
+            ..Default::default()
+        };
+        let doc = Document::open_with(&path, Language::Rust, &options).unwrap();
+        assert!(doc.is_decoded());
+
+        let workspace = Workspace::new(Language::Rust, options);
+        let change = Change::new(doc.range(0..2), "pub fn".to_owned());
+        let result = workspace.changes([(doc, vec![change])]);
+        assert!(matches!(result, Err(Error::Decoded(_))));
+    }
+
+    #[test]
+    fn range_in_document() {
+        let script = r#"document.edit(range(1, 4, 1, 7), "new");"#;
+        let changes = run("fn old() {}\n", script).unwrap();
+        assert_eq!(changes[0].document().content(), "fn new() {}\n");
+    }
+
+    #[test]
+    fn range_outside_of_file() {
+        let script = r#"
+            workspace.create("b.rs", "fn b() {}\n");
+            workspace.edit("b.rs", range(1, 20, 1, 21), "x");
+        "#;
+        assert!(matches!(run("fn a() {}\n", script), Err(Error::Range(_))));
+    }
+}